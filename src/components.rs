@@ -1,14 +1,116 @@
 use crate::{
-    prelude::{InsertResult, TimewarpError},
+    prelude::{InsertResult, TimewarpError, TimewarpInput, TimewarpInterpolate},
     FrameBuffer, FrameNumber, TimewarpComponent,
 };
-use bevy::prelude::*;
+use bevy::{
+    ecs::{component::ComponentId, system::EntityCommands},
+    prelude::*,
+    utils::HashMap,
+};
+use std::{any::TypeId, ops::Range, sync::Arc};
 
 /// entities with NoRollback are ignored, even if they have components which
 /// have been registered for rollback.
 #[derive(Component)]
 pub struct NoRollback;
 
+/// A deterministic id for an entity spawned by game logic, eg `hash(player_id, shot_number)`.
+/// Entities spawned with a [`KeyedSpawner`](crate::prelude::KeyedSpawner) get one, and
+/// resimulations that spawn the same key reuse the same `Entity`.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpawnKey(pub u64);
+
+/// Marks an entity the client spawned before the server told us about it, eg a bullet we fired.
+///
+/// When an entity with a matching [`ServerSpawnKey`] arrives, it takes over the predicted
+/// entity's registered components and their history, and the predicted entity is despawned.
+/// If nothing matches within `TimewarpConfig::predicted_spawn_timeout` frames, the prediction
+/// was wrong, and the predicted entity is despawned.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct PredictedSpawn {
+    pub key: SpawnKey,
+    /// the frame we spawned it, filled in with the current frame if None.
+    pub frame: Option<FrameNumber>,
+}
+
+impl PredictedSpawn {
+    pub fn new(key: SpawnKey) -> Self {
+        Self { key, frame: None }
+    }
+}
+
+/// Insert on a server-replicated entity to match it with the client's [`PredictedSpawn`]
+/// with the same key. It's removed once they are matched.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ServerSpawnKey(pub SpawnKey);
+
+/// Selects how an entity with rollback-registered components handles `ServerSnapshot`s.
+/// Entities without this component are `Predicted`.
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PredictionMode {
+    /// Simulated ahead of the server, rolling back when a snapshot disagrees with our prediction.
+    /// eg, the local player and physics objects.
+    #[default]
+    Predicted,
+    /// Shown `delay` frames behind the present, interpolating between the snapshots either side
+    /// of that frame. Never triggers or takes part in rollbacks. eg, remote players.
+    /// Requires [`register_interpolation`][crate::prelude::TimewarpTraits::register_interpolation].
+    Interpolated { delay: FrameNumber },
+    /// Snapshot values are applied as soon as they arrive, without rolling back.
+    /// eg, cosmetic entities.
+    Snapped,
+}
+
+impl PredictionMode {
+    pub fn is_predicted(&self) -> bool {
+        *self == Self::Predicted
+    }
+}
+
+/// Marks component T on this entity as owned by the client for a range of frames, eg camera aim.
+/// `ServerSnapshot<T>` values for owned frames are still recorded, but are never applied and
+/// never trigger a rollback.
+///
+/// Transfer authority at a given frame with `from_frame` and `release_at`.
+#[derive(Component, Debug, Clone)]
+pub struct LocalAuthority<T: TimewarpComponent> {
+    /// first frame we own T for
+    pub from_frame: FrameNumber,
+    /// first frame the server owns T for again, if any
+    pub until_frame: Option<FrameNumber>,
+    _phantom: std::marker::PhantomData<T>,
+}
+impl<T: TimewarpComponent> Default for LocalAuthority<T> {
+    fn default() -> Self {
+        Self::from_frame(0)
+    }
+}
+impl<T: TimewarpComponent> LocalAuthority<T> {
+    /// we own T for all frames
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// we own T from `frame` onwards
+    pub fn from_frame(frame: FrameNumber) -> Self {
+        Self {
+            from_frame: frame,
+            until_frame: None,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+    /// hand authority back to the server from `frame` onwards
+    pub fn release_at(&mut self, frame: FrameNumber) {
+        self.until_frame = Some(frame);
+    }
+    pub fn owns_at(&self, frame: FrameNumber) -> bool {
+        let before_until = match self.until_frame {
+            Some(until) => frame < until,
+            None => true,
+        };
+        frame >= self.from_frame && before_until
+    }
+}
+
 /// Added to every entity for metrics
 #[derive(Component, Debug)]
 pub struct TimewarpStatus {
@@ -70,18 +172,148 @@ impl<T: TimewarpComponent> InsertComponentAtFrame<T> {
 ///
 /// I use this for blueprints. The blueprint assembly function runs during rollback and
 /// adds the various timewarp-registered (and other) components to the entity during rollback.
+///
+/// Clients normally assemble blueprints the frame after the server did, since the replicated
+/// component values are post-physics values for `frame`. If you also provide the spawn-frame
+/// state of the replicated components with `with_spawn_state`, we assemble on `frame` itself,
+/// and the replicated values for `frame` are applied after physics, so nothing needs correcting.
 #[derive(Component, Debug)]
 pub struct AssembleBlueprintAtFrame<T: Component + std::fmt::Debug + Clone> {
     pub component: T,
     pub frame: FrameNumber,
+    pub spawn_state: Option<BlueprintSpawnState>,
+    /// inserted by our own game logic, rather than the server. See [`PredictedBlueprint`].
+    pub predicted: bool,
 }
 impl<T: Component + std::fmt::Debug + Clone> AssembleBlueprintAtFrame<T> {
     pub fn new(frame: FrameNumber, component: T) -> Self {
-        Self { component, frame }
+        Self {
+            component,
+            frame,
+            spawn_state: None,
+            predicted: false,
+        }
+    }
+    /// mark a blueprint our game logic spawned, which a rollback may find was mispredicted.
+    pub fn predicted(mut self) -> Self {
+        self.predicted = true;
+        self
+    }
+    /// `bundle` holds the replicated components as they were when the server assembled the
+    /// blueprint, before physics ran.
+    pub fn with_spawn_state<B: Bundle + Clone>(mut self, bundle: B) -> Self {
+        self.spawn_state = Some(BlueprintSpawnState::new(bundle));
+        self
     }
     pub fn type_name(&self) -> &str {
         std::any::type_name::<T>()
     }
+    /// if we should unwrap this blueprint now, in the prefix before simulating `current_frame + 1`.
+    /// returns true if we should also insert the spawn state.
+    ///
+    /// Servers never rollback to a blueprint's frame, so they unwrap past ones straight away.
+    pub(crate) fn unwrap_now(&self, current_frame: FrameNumber, is_server: bool) -> Option<bool> {
+        if self.spawn_state.is_some() && self.frame == current_frame + 1 {
+            return Some(true);
+        }
+        if is_server {
+            return (self.frame <= current_frame).then_some(false);
+        }
+        // yes, blueprints assembled 1 frame late on clients. see NOTES
+        (self.frame == current_frame).then_some(false)
+    }
+}
+
+/// Added to entities assembled from a predicted [`AssembleBlueprintAtFrame`].
+///
+/// If a rollback resimulates the assembly frame, the entity loses the blueprint, what was
+/// assembled, and its registered components, and is given a [`PendingBlueprintAssembly`]. If the
+/// resimulation doesn't assemble the blueprint again, the entity is despawned when the rollback
+/// completes, and a [`BlueprintMispredicted`] event is sent.
+///
+/// [`BlueprintMispredicted`]: crate::prelude::BlueprintMispredicted
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct PredictedBlueprint {
+    /// the first frame simulated with the assembled blueprint
+    pub frame: FrameNumber,
+}
+
+/// A predicted blueprint, assembled during frames we are resimulating, that hasn't been
+/// assembled again yet.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct PendingBlueprintAssembly {
+    pub frame: FrameNumber,
+}
+
+/// Added to entities assembled by a blueprint assembler registered with
+/// `register_blueprint_with_assembler::<T>`. Records which components the assembler added,
+/// so that if a rollback resimulates the assembly frame, they are removed and the blueprint
+/// is wrapped up again to be reassembled at the right point. Components the entity already had
+/// are put back with the values they had before assembly, if they are reflectable.
+#[derive(Component, Debug)]
+pub struct AssembledBlueprint<T: Component + std::fmt::Debug + Clone> {
+    /// the frame of the [`AssembleBlueprintAtFrame`] we unwrapped
+    pub(crate) blueprint_frame: FrameNumber,
+    /// the frame simulated right after assembly
+    pub(crate) assembled_frame: FrameNumber,
+    pub(crate) spawn_state: Option<BlueprintSpawnState>,
+    pub(crate) components: Vec<ComponentId>,
+    /// values of components the entity already had, which the assembled bundle overwrote
+    pub(crate) replaced: Vec<(ComponentId, Box<dyn Reflect>)>,
+    /// removes the bundle the assembler returned
+    pub(crate) remove: fn(&mut EntityWorldMut),
+    _phantom: std::marker::PhantomData<T>,
+}
+
+impl<T: Component + std::fmt::Debug + Clone> AssembledBlueprint<T> {
+    pub(crate) fn new(
+        blueprint_frame: FrameNumber,
+        assembled_frame: FrameNumber,
+        spawn_state: Option<BlueprintSpawnState>,
+        components: Vec<ComponentId>,
+        replaced: Vec<(ComponentId, Box<dyn Reflect>)>,
+        remove: fn(&mut EntityWorldMut),
+    ) -> Self {
+        Self {
+            blueprint_frame,
+            assembled_frame,
+            spawn_state,
+            components,
+            replaced,
+            remove,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+    /// the first frame simulated with the assembled components
+    pub fn assembled_frame(&self) -> FrameNumber {
+        self.assembled_frame
+    }
+    /// the components added by assembly
+    pub fn components(&self) -> &[ComponentId] {
+        &self.components
+    }
+}
+
+/// The replicated components of a blueprint as they were on the frame the server spawned it.
+#[derive(Clone)]
+pub struct BlueprintSpawnState(Arc<dyn Fn(&mut EntityCommands) + Send + Sync>);
+
+impl BlueprintSpawnState {
+    pub fn new<B: Bundle + Clone>(bundle: B) -> Self {
+        Self(Arc::new(move |ec: &mut EntityCommands| {
+            ec.insert(bundle.clone());
+        }))
+    }
+    /// insert the spawn-frame components
+    pub fn apply(&self, ec: &mut EntityCommands) {
+        (self.0)(ec);
+    }
+}
+
+impl std::fmt::Debug for BlueprintSpawnState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BlueprintSpawnState")
+    }
 }
 
 /// entities with components that were registered with error correction logging will receive
@@ -96,17 +328,68 @@ pub struct TimewarpCorrection<T: TimewarpComponent> {
     pub frame: FrameNumber,
 }
 
-/// Buffers the last few authoritative component values received from the server
+/// On servers with a `late_input_window`, records which frames of T were changed by the most
+/// recent resimulation, so you can rebroadcast the corrected values from the ComponentHistory<T>.
+/// Look for Changed<ServerCorrection<T>> in your networking systems.
+#[derive(Component, Debug, Clone)]
+pub struct ServerCorrection<T: TimewarpComponent> {
+    /// frames for which the resimulated value of T differs from the one we previously simulated
+    pub frames: Vec<FrameNumber>,
+    /// the range of the rollback that produced these corrections
+    pub rollback_range: Range<FrameNumber>,
+    _phantom: std::marker::PhantomData<T>,
+}
+impl<T: TimewarpComponent> ServerCorrection<T> {
+    pub fn new(rollback_range: Range<FrameNumber>) -> Self {
+        Self {
+            frames: Vec::new(),
+            rollback_range,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+/// Buffers the last few authoritative component values received from the server,
+/// and the frames at which the server removed the component.
 #[derive(Component)]
 pub struct ServerSnapshot<T: TimewarpComponent> {
     pub values: FrameBuffer<T>,
+    /// tombstones: frames at which T was removed, oldest first.
+    /// T is not alive at these frames, same as `ComponentHistory::report_death_at_frame`.
+    pub removals: Vec<FrameNumber>,
 }
 impl<T: TimewarpComponent> ServerSnapshot<T> {
     pub fn with_capacity(len: usize) -> Self {
         Self {
             values: FrameBuffer::with_capacity(len, "SS"),
+            removals: Vec::new(),
         }
     }
+    /// record that the server removed T at `frame`.
+    /// If we predicted T was still alive, this will trigger a rollback.
+    pub fn insert_removal(&mut self, frame: FrameNumber) {
+        if self.removals.contains(&frame) {
+            return;
+        }
+        self.removals.push(frame);
+        self.removals.sort_unstable();
+        // same range as the values buffer
+        let newest = *self.removals.last().expect("just pushed");
+        let oldest = newest.saturating_sub(self.values.capacity() as FrameNumber);
+        self.removals.retain(|f| *f > oldest);
+    }
+    pub fn newest_removal(&self) -> Option<FrameNumber> {
+        self.removals.last().copied()
+    }
+    /// true if the most recent thing the server told us about T at or before `frame`
+    /// was that it was removed.
+    pub fn removed_at(&self, frame: FrameNumber) -> bool {
+        let Some(removal) = self.removals.iter().rev().find(|f| **f <= frame) else {
+            return false;
+        };
+        // a removal and a value for the same frame means it was removed at the end of it
+        !(*removal + 1..=frame).any(|f| self.values.get(f).is_some())
+    }
     pub fn at_frame(&self, frame: FrameNumber) -> Option<&T> {
         self.values.get(frame)
     }
@@ -126,6 +409,24 @@ impl<T: TimewarpComponent> ServerSnapshot<T> {
     }
 }
 
+impl<T: TimewarpComponent + TimewarpInterpolate> ServerSnapshot<T> {
+    /// Value at `frame`, interpolated between the nearest snapshots either side of it.
+    /// Holds the newest value if `frame` is newer than every snapshot.
+    pub fn interpolated_at(&self, frame: FrameNumber) -> Option<T> {
+        let newest = self.values.newest_frame();
+        let (from_frame, from) = (self.values.oldest_frame()..=frame.min(newest))
+            .rev()
+            .find_map(|f| self.values.get(f).map(|v| (f, v)))?;
+        let Some((to_frame, to)) =
+            (frame + 1..=newest).find_map(|f| self.values.get(f).map(|v| (f, v)))
+        else {
+            return Some(from.clone());
+        };
+        let t = (frame - from_frame) as f32 / (to_frame - from_frame) as f32;
+        Some(from.interpolate(to, t))
+    }
+}
+
 /// used to record component birth/death ranges in ComponentHistory.
 /// (start, end) – can be open-ended if end is None.
 pub type FrameRange = (FrameNumber, Option<FrameNumber>);
@@ -168,6 +469,15 @@ impl<T: TimewarpComponent> ComponentHistory<T> {
     pub fn at_frame(&self, frame: FrameNumber) -> Option<&T> {
         self.values.get(frame)
     }
+    /// like `at_frame`, but respects `alive_ranges`, so returns None if the component was
+    /// dead at this frame, even if a stale value is still buffered.
+    pub fn alive_value_at_frame(&self, frame: FrameNumber) -> Option<&T> {
+        if self.alive_at_frame(frame) {
+            self.values.get(frame)
+        } else {
+            None
+        }
+    }
     // adding entity just for debugging print outs.
     pub fn insert(
         &mut self,
@@ -209,11 +519,46 @@ impl<T: TimewarpComponent> ComponentHistory<T> {
             self.values.get(frame).is_some(),
             "No stored component value when reporting birth @ {frame}"
         );
-        if self.alive_ranges.last().unwrap().1 == Some(frame) {
+        if self
+            .alive_ranges
+            .last()
+            .is_some_and(|range| range.1 == Some(frame))
+        {
             return;
         }
         self.alive_ranges.push((frame, None));
     }
+    /// forget the component was alive at `frame` or later, for predictions that a rollback is
+    /// undoing. Unlike a death, the resimulation can report a birth at `frame` again.
+    pub fn forget_lives_from(&mut self, frame: FrameNumber) {
+        self.alive_ranges.retain(|(start, _)| *start < frame);
+        if let Some(range) = self.alive_ranges.last_mut() {
+            let ends_later = match range.1 {
+                Some(end) => end > frame,
+                None => true,
+            };
+            if ends_later {
+                range.1 = Some(frame);
+            }
+        }
+        trace!(
+            "forgot component lives from {frame} {:?} --> {:?}",
+            std::any::type_name::<T>(),
+            self.alive_ranges
+        );
+    }
+    /// forget a death reported at `frame`, if it was the most recent one.
+    pub fn undo_death_at_frame(&mut self, frame: FrameNumber) {
+        if let Some(range) = self.alive_ranges.last_mut() {
+            if range.1 == Some(frame) {
+                trace!(
+                    "undoing component death @ {frame} {:?}",
+                    std::any::type_name::<T>()
+                );
+                range.1 = None;
+            }
+        }
+    }
     pub fn report_death_at_frame(&mut self, frame: FrameNumber) {
         // currently after rollback we get (harmless?) erroneous RemovedComponent<> reports
         // so we just supress here for now.
@@ -239,3 +584,241 @@ impl<T: TimewarpComponent> ComponentHistory<T> {
         );
     }
 }
+
+/// Records the `Parent` of an entity for the last few frames, so rollbacks restore the hierarchy.
+/// Added to child entities with registered components, by `app.register_rollback_hierarchy()`.
+#[derive(Component, Debug)]
+pub struct ParentHistory {
+    /// None means the entity had no parent at that frame
+    pub values: FrameBuffer<Option<Entity>>,
+}
+
+impl ParentHistory {
+    pub fn with_capacity(len: usize, frame: FrameNumber, parent: Option<Entity>) -> Self {
+        let mut values = FrameBuffer::with_capacity(len, "PH");
+        values
+            .insert(frame, parent)
+            .expect("Should always be able to insert the first parent");
+        Self { values }
+    }
+    /// the parent at `frame`, or None if we don't know.
+    pub fn parent_at_frame(&self, frame: FrameNumber) -> Option<Option<Entity>> {
+        self.values.get(frame).copied()
+    }
+}
+
+/// A type-erased component value, for components registered at runtime with
+/// `register_rollback_by_type_id` or `register_rollback_by_type_path`.
+/// Values are compared with `reflect_partial_eq`.
+pub struct ReflectValue(pub Box<dyn Reflect>);
+
+impl ReflectValue {
+    /// the `TypeId` of the component this value represents, which also works for dynamic values
+    /// like a `DynamicStruct` deserialized by a mod.
+    pub fn type_id(&self) -> TypeId {
+        self.0
+            .get_represented_type_info()
+            .map(|info| info.type_id())
+            .unwrap_or_else(|| self.0.as_any().type_id())
+    }
+}
+
+impl Clone for ReflectValue {
+    fn clone(&self) -> Self {
+        Self(self.0.clone_value())
+    }
+}
+
+impl PartialEq for ReflectValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.0
+            .reflect_partial_eq(other.0.as_reflect())
+            .unwrap_or(false)
+    }
+}
+
+impl std::fmt::Debug for ReflectValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.debug(f)
+    }
+}
+
+/// Like a [`ComponentHistory`] for every component registered at runtime, keyed by `TypeId`.
+/// Added to entities that have any of them.
+#[derive(Component, Debug)]
+pub struct DynamicComponentHistory {
+    /// None means the entity didn't have the component at that frame
+    pub values: HashMap<TypeId, FrameBuffer<Option<ReflectValue>>>,
+    capacity: usize,
+}
+
+impl DynamicComponentHistory {
+    pub fn with_capacity(len: usize) -> Self {
+        Self {
+            values: HashMap::default(),
+            capacity: len,
+        }
+    }
+    /// the value at `frame`, or None if the entity didn't have it, or we don't know.
+    pub fn value_at_frame(&self, type_id: TypeId, frame: FrameNumber) -> Option<&dyn Reflect> {
+        self.values
+            .get(&type_id)
+            .and_then(|values| values.get(frame))
+            .and_then(|value| value.as_ref())
+            .map(|value| value.0.as_ref())
+    }
+    pub(crate) fn insert(
+        &mut self,
+        type_id: TypeId,
+        type_path: &str,
+        frame: FrameNumber,
+        value: Option<ReflectValue>,
+    ) -> Result<InsertResult, TimewarpError> {
+        if value.is_none() && !self.values.contains_key(&type_id) {
+            return Ok(InsertResult::Identical);
+        }
+        let capacity = self.capacity;
+        self.values
+            .entry(type_id)
+            .or_insert_with(|| FrameBuffer::with_capacity(capacity, type_path))
+            .insert(frame, value)
+    }
+}
+
+/// Like a [`ServerSnapshot`] for every component registered at runtime, keyed by `TypeId`.
+/// Your network code inserts authoritative values here.
+#[derive(Component, Debug)]
+pub struct DynamicServerSnapshot {
+    pub values: HashMap<TypeId, FrameBuffer<ReflectValue>>,
+    capacity: usize,
+    /// inserted since timewarp last checked
+    pending: Vec<(TypeId, FrameNumber)>,
+}
+
+impl DynamicServerSnapshot {
+    pub fn with_capacity(len: usize) -> Self {
+        Self {
+            values: HashMap::default(),
+            capacity: len,
+            pending: Vec::new(),
+        }
+    }
+    /// insert the authoritative value of a component at `frame`
+    pub fn insert(
+        &mut self,
+        frame: FrameNumber,
+        value: Box<dyn Reflect>,
+    ) -> Result<InsertResult, TimewarpError> {
+        let value = ReflectValue(value);
+        let type_id = value.type_id();
+        let capacity = self.capacity;
+        let ret = self
+            .values
+            .entry(type_id)
+            .or_insert_with(|| FrameBuffer::with_capacity(capacity, "DSS"))
+            .insert(frame, value)?;
+        self.pending.push((type_id, frame));
+        Ok(ret)
+    }
+    pub fn at_frame(&self, type_id: TypeId, frame: FrameNumber) -> Option<&ReflectValue> {
+        self.values
+            .get(&type_id)
+            .and_then(|values| values.get(frame))
+    }
+    pub(crate) fn take_pending(&mut self) -> Vec<(TypeId, FrameNumber)> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Buffers a player's inputs for the last few frames, indexed by the frame they apply to.
+///
+/// Your networking code inserts inputs as they arrive, and your game systems read the input
+/// for the current frame with `input_at(game_clock.frame())`, including during rollback.
+/// If an input arrives for a frame we already simulated, timewarp requests a rollback, as long
+/// as it's within `TimewarpConfig::input_acceptance_window`.
+///
+/// In peer-to-peer mode, simulate with `predicted_input_at` instead, and we only rollback when
+/// an arriving input differs from what was predicted.
+#[derive(Component, Debug)]
+pub struct InputBuffer<I: TimewarpInput> {
+    pub inputs: FrameBuffer<I>,
+    /// oldest frame an input was inserted for, since timewarp last checked.
+    oldest_new_frame: Option<FrameNumber>,
+    /// oldest frame an input was inserted for that differs from what `predicted_input_at` would
+    /// have returned, since timewarp last checked.
+    oldest_mispredicted_frame: Option<FrameNumber>,
+    /// newest frame for which we have every input, with no gaps.
+    confirmed_frame: Option<FrameNumber>,
+    /// first frame we expect an input for
+    start_frame: FrameNumber,
+}
+impl<I: TimewarpInput> InputBuffer<I> {
+    /// inputs are expected from frame 1, the first frame the `GameClock` simulates.
+    pub fn with_capacity(len: usize) -> Self {
+        Self::with_capacity_starting_at(len, 1)
+    }
+    /// For players who join mid-game: `start_frame` is the first frame they send inputs for.
+    pub fn with_capacity_starting_at(len: usize, start_frame: FrameNumber) -> Self {
+        let mut inputs = FrameBuffer::with_capacity(len, "IB");
+        inputs.start_at(start_frame);
+        Self {
+            inputs,
+            oldest_new_frame: None,
+            oldest_mispredicted_frame: None,
+            confirmed_frame: None,
+            start_frame,
+        }
+    }
+    pub fn input_at(&self, frame: FrameNumber) -> Option<&I> {
+        self.inputs.get(frame)
+    }
+    /// The input for `frame` if we have it, otherwise repeats the most recent earlier input.
+    /// In peer-to-peer mode, use this when simulating remote players whose inputs haven't
+    /// arrived yet – if the real input turns out different, we rollback.
+    pub fn predicted_input_at(&self, frame: FrameNumber) -> Option<&I> {
+        let newest = frame.min(self.inputs.newest_frame());
+        (self.inputs.oldest_frame()..=newest)
+            .rev()
+            .find_map(|f| self.inputs.get(f))
+    }
+    /// newest frame for which all inputs have arrived, counting from the start frame.
+    /// None until the input for the start frame arrives.
+    pub fn confirmed_frame(&self) -> Option<FrameNumber> {
+        self.confirmed_frame
+    }
+    pub fn insert(&mut self, frame: FrameNumber, input: I) -> Result<InsertResult, TimewarpError> {
+        let mispredicted = self.predicted_input_at(frame) != Some(&input);
+        let ret = self.inputs.insert(frame, input)?;
+        if !matches!(ret, InsertResult::Identical) {
+            self.oldest_new_frame = Some(self.oldest_new_frame.map_or(frame, |f| f.min(frame)));
+        }
+        if mispredicted {
+            self.oldest_mispredicted_frame = Some(
+                self.oldest_mispredicted_frame
+                    .map_or(frame, |f| f.min(frame)),
+            );
+        }
+        // never advance past a frame whose input is still missing
+        let mut confirmed = self
+            .confirmed_frame
+            .unwrap_or_else(|| self.start_frame.saturating_sub(1));
+        while self.inputs.get(confirmed + 1).is_some() {
+            confirmed += 1;
+        }
+        if confirmed >= self.start_frame {
+            self.confirmed_frame = Some(confirmed);
+        }
+        Ok(ret)
+    }
+    pub fn type_name(&self) -> &str {
+        std::any::type_name::<I>()
+    }
+    /// returns the oldest frame an input was inserted for since this was last called
+    pub(crate) fn take_oldest_new_frame(&mut self) -> Option<FrameNumber> {
+        self.oldest_new_frame.take()
+    }
+    /// returns the oldest mispredicted frame since this was last called
+    pub(crate) fn take_oldest_mispredicted_frame(&mut self) -> Option<FrameNumber> {
+        self.oldest_mispredicted_frame.take()
+    }
+}
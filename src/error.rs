@@ -1,5 +1,11 @@
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TimewarpError {
     FrameTooOld,
     FrameTooOldSnapped,
+    /// for operations that only make sense for the current or past frames
+    FrameInFuture,
+    /// removing a component at a past frame needs its `ComponentHistory`
+    NoComponentHistory,
+    /// the entity was despawned before the command was applied
+    NoSuchEntity,
 }
@@ -59,6 +59,10 @@ where
         }
     }
 
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
     pub fn current_range(&self) -> Range<FrameNumber> {
         Range {
             start: self.oldest_frame(),
@@ -78,6 +82,15 @@ where
             .saturating_sub(self.entries.len().saturating_sub(1) as FrameNumber)
     }
 
+    /// makes `frame` the oldest frame that can be inserted into this empty buffer, even if newer
+    /// frames are inserted first.
+    pub(crate) fn start_at(&mut self, frame: FrameNumber) {
+        debug_assert!(self.entries.is_empty(), "start_at on a non-empty buffer");
+        self.entries.clear();
+        self.entries.push_front(None);
+        self.front_frame = frame.saturating_sub(1);
+    }
+
     /// removes entries for frames larger than `frame`
     /// buffer could contain fewer than `capacity` values after this operation.
     pub fn remove_entries_newer_than(&mut self, frame: FrameNumber) {
@@ -174,7 +187,7 @@ where
             panic!("Shouldn't get here");
         }
 
-        if self.front_frame == 0 || frame == self.front_frame + 1 {
+        if self.entries.is_empty() || frame == self.front_frame + 1 {
             // no gaps.
         } else {
             // so we are inserting a frame greater than front_frame.
@@ -42,15 +42,26 @@
 //! // register components that should be buffered and rolled back as needed:
 //! app.register_rollback::<MyComponent>();
 //! app.register_rollback::<Position>();
+//! // or several at once, restored and recorded together by one system each:
+//! app.register_rollback_bundle::<(Velocity, Health, Shield)>();
+//! // snapshots for these are applied directly, mismatches never trigger a rollback:
+//! app.register_rollback_snap_only::<Scoreboard>();
 //! // etc..
 //! ```
 //!
+//! The `TimewarpRegistry` resource lists every registered type with its options, and
+//! `TimewarpRegistry::memory_usage(world)` reports how much its buffers use. Registering the same
+//! type twice, including as both a static and a dynamic rollback component, or a blueprint with
+//! and without an assembler, logs a warning and is otherwise ignored. The first registration's
+//! options are kept.
+//!
 //! Any entity that has a `T` Component will automatically be given a [`ComponentHistory<T>`] and
 //! [`ServerSnapshot<T>`] component.
 //!
 //! `ComponentHistory` is a circular buffer of the last N frames of component values.
 //! This is logged every frame automatically, so is mostly your client predicted values.
-//! You typically won't need to interact with this.
+//! You typically won't need to interact with this, but if you want to read component values at
+//! past frames (eg, for debugging or lag compensation), use the [`HistoricalQuery`] system param.
 //!
 //! `ServerSnapshot` is a buffer of the last few authoritative component values, typically what
 //! you received from the game server. Your network system will need to add new values to this.
@@ -70,6 +81,14 @@
 //! }
 //!```
 //!
+//! If the server removes a component, record it with `ServerSnapshot::insert_removal(frame)`.
+//! If we predicted the component was still alive at that frame, it triggers a rollback.
+//!
+//! If each network packet holds all the updates for one server frame, wrap processing it in
+//! `SnapshotBatches::begin(frame)` and `end(complete)`. Timewarp then knows which frame to
+//! rollback to, instead of relying on the configured `RollbackConsolidationStrategy`. Once you use
+//! batches, snapshots for any frame being resimulated are applied as it is resimulated.
+//!
 //! Alternatively, and especially if you are inserting a component your entity has never had before,
 //! meaning there will be no `ServerSnapshot<T>` component, insert components in the past like this:
 //!
@@ -79,6 +98,17 @@
 //! commands.entity(e1).insert(historical_component);
 //! ```
 //!
+//! The `TimewarpCommands` extension to `EntityCommands` covers every past-frame operation:
+//! `insert_at_frame`, `remove_at_frame`, `despawn_at_frame` and `assemble_blueprint_at_frame`.
+//! Commands that fail, e.g. because the frame is older than the rollback window, send a
+//! `TimewarpCommandError` event. To spawn an entity that has existed since a past frame, use
+//! `commands.spawn_at_frame(frame, bundle)` from `TimewarpSpawnCommands`. Registered components in
+//! the bundle get their history from that frame, with a single rollback.
+//!
+//! ```rust,ignore
+//! commands.entity(e1).insert_at_frame(123, MyComponent);
+//! ```
+//!
 //! ### Systems configuration
 //!
 //! Divide up your game systems so that during a rollback you still apply stored player input,
@@ -132,6 +162,127 @@
 //! Typically this would be useful for some visual smoothing - you might gradually blend over the
 //! error distance with your sprite, even though the underlying physical simulation snapped correct.
 //!
+//! # Prediction modes
+//!
+//! By default every entity is predicted. Add a `PredictionMode` component to change that per
+//! entity: `Interpolated { delay }` shows remote players `delay` frames in the past, interpolating
+//! between `ServerSnapshot`s (implement `TimewarpInterpolate` and call `register_interpolation`),
+//! and `Snapped` applies snapshot values as they arrive, for cosmetic entities. Neither triggers
+//! nor takes part in rollbacks, so your game logic should only simulate predicted entities.
+//!
+//! Components owned by the client, like camera aim, can be marked with `LocalAuthority<T>`.
+//! Snapshots for frames we own are recorded in the `ServerSnapshot`, but are never applied and
+//! never trigger a rollback. Use `release_at(frame)` to hand authority back to the server.
+//!
+//! # Server mode
+//!
+//! On the server you usually only want component history, for lag compensation or snapshot
+//! baselines. Configure timewarp with `TimewarpMode::Server` and it will only record history and
+//! handle despawns – no `ServerSnapshot`s are added, and it never rolls back. Blueprints for past
+//! frames are unwrapped straight away instead of rolling back to assemble them.
+//!
+//! ```rust,ignore
+//! let config = TimewarpConfig::new(MySets::First, MySets::Last).with_mode(TimewarpMode::Server);
+//! app.add_plugins(TimewarpPlugin::new(config));
+//! ```
+//!
+//! ## Late inputs
+//!
+//! Servers can optionally resimulate when client inputs arrive late. Put inputs in an
+//! `InputBuffer<I>` on the entity they control, register the input type, and set a window:
+//!
+//! ```rust,ignore
+//! let config = TimewarpConfig::new(MySets::First, MySets::Last)
+//!     .with_mode(TimewarpMode::Server)
+//!     .with_late_input_window(3)
+//!     .with_consolidation_strategy(RollbackConsolidationStrategy::Oldest);
+//! // ..
+//! app.register_input::<MyInput>();
+//! ```
+//!
+//! An input for a frame we already simulated, up to `late_input_window` frames ago, triggers a
+//! rollback to that frame. Older inputs are dropped and counted in `RollbackStats`. Late inputs
+//! for different players can arrive for different frames in one tick, so the `Oldest`
+//! consolidation strategy is required.
+//! Entities whose components changed during resimulation get a `ServerCorrection<T>` listing
+//! the frames that differ, so you know what to rebroadcast.
+//!
+//! # Lag compensation
+//!
+//! On the server, you can temporarily put registered components back to how they were at a past
+//! frame, eg to check a hit the way the shooter saw it:
+//!
+//! ```rust,ignore
+//! let hit = world.with_rewound_to(shot_frame, |world, rewound| check_hit(world, rewound, shot))?;
+//! ```
+//!
+//! Present values are restored afterwards, without touching history or change detection.
+//! Entities that weren't alive at `shot_frame` keep their present values, so skip any not in
+//! `rewound`. It fails if `shot_frame` is outside the rollback window.
+//! Use [`RewindFilter`] with `with_rewound_to_filtered` to only rewind some entities or components.
+//!
+//! # Registering components at runtime
+//!
+//! Components from data, mods or scripts can't be registered with `register_rollback::<T>()`.
+//! If they are in the `AppTypeRegistry` with `#[reflect(Component)]`, register them with
+//! `app.register_rollback_by_type_path("my_mod::Mana")` or `register_rollback_by_type_id`
+//! instead. Their history is stored type-erased in a `DynamicComponentHistory`, and the server's
+//! values go in a `DynamicServerSnapshot`, otherwise they are recorded, snapped and rolled back
+//! like any other registered component, including by despawns, `despawn_at_frame`,
+//! `spawn_at_frame`, keyed spawns and predicted spawns. Content loaded after startup can register
+//! from the `World` with `TimewarpWorldRegistration`, eg `world.register_rollback_by_type_path(..)`
+//! in an exclusive system. See the `dynamic_registration` test.
+//!
+//! # Spawning during rollback
+//!
+//! If a game system spawns an entity, eg a bullet, resimulating that frame would spawn it again.
+//! Spawn with a `KeyedSpawner` instead, passing a `SpawnKey` your game logic derives
+//! deterministically, like the player id and shot number. Spawning the same key during a
+//! resimulation reuses the original `Entity`, and keyed entities that the resimulation didn't
+//! spawn again are despawned when the rollback completes. Until it is spawned again, a keyed
+//! entity loses its registered components along with their histories, which only held the
+//! predictions being undone. Children spawned along with it are despawned, since the
+//! resimulation will spawn them again. See the `keyed_spawns` test.
+//!
+//! To match an entity the client predicted with the one the server replicates for it later,
+//! give the client's entity a `PredictedSpawn` and the server's a `ServerSpawnKey` with the same
+//! key. When the server entity arrives, it takes over the predicted entity's registered components
+//! and their history, and the predicted entity is despawned. Predictions nothing matches within
+//! `TimewarpConfig::predicted_spawn_timeout` frames are despawned. See the `predicted_spawns` test.
+//!
+//! Blueprints your game logic predicts can be wrong too. Spawn them with a `KeyedSpawner`, and
+//! mark the `AssembleBlueprintAtFrame` with `.predicted()`. If a rollback resimulates the frame it
+//! was assembled on and doesn't assemble it again, the entity is despawned and a
+//! `BlueprintMispredicted` event is sent. See the `blueprint_mispredictions` test.
+//!
+//! # Peer-to-peer mode
+//!
+//! Without an authoritative server, configure `TimewarpMode::PeerToPeer`. Every peer simulates
+//! the whole game, and confirmed remote inputs are the only authority – there are no
+//! `ServerSnapshot`s. Register your input type, and simulate remote players using
+//! `InputBuffer::predicted_input_at`, which repeats their last known input until the real one
+//! arrives. If it differs from what we predicted, we rollback to that frame. Peers send inputs for
+//! different frames, so the `Oldest` consolidation strategy is required:
+//!
+//! ```rust,ignore
+//! let config = TimewarpConfig::new(MySets::First, MySets::Last)
+//!     .with_mode(TimewarpMode::PeerToPeer)
+//!     .with_consolidation_strategy(RollbackConsolidationStrategy::Oldest);
+//! ```
+//!
+//! The `ConfirmedFrame` resource holds the newest frame for which every peer's inputs have
+//! arrived, with no gaps. Inputs are expected from frame 1, so give players who join later an
+//! `InputBuffer::with_capacity_starting_at(len, join_frame)`. Despawned entities are despawned
+//! for real once their despawn frame is confirmed.
+//!
+//! # Lockstep mode
+//!
+//! With `TimewarpMode::Lockstep`, nothing is predicted and we never rollback. Inputs go in the
+//! same `InputBuffer`s, but each tick the timewarp sets and your `first_set` and `last_set` only
+//! run if every player's input for the next frame has arrived – otherwise the tick is skipped, so
+//! `GameClock` stalls. Add the `lockstep_ready` run condition to any other game systems.
+//! `LockstepStats` counts stalled ticks. Component history is recorded as usual.
+//!
 //! ## Testing various edge cases
 //!
 //! TODO: I don't know how to link rustdocs to integration tests..
@@ -147,10 +298,25 @@
 //! the entity immediately by removing all its registered components, then does the actual despawn
 //! after `rollback_window` frames have elapsed.
 //!
+//! Despawns are treated as predictions of your game logic, e.g. a bullet hitting something.
+//! Rolling back to before the despawn frame removes the marker and revives the components, so
+//! the entity only dies again if the resimulation inserts another marker. See the
+//! `predicted_despawns` test.
+//!
+//! If the server tells you an entity was despawned, use
+//! `commands.entity(id).despawn_at_frame(frame)` instead. It marks the registered components
+//! dead at that frame and rolls back, so the entity disappears at the right point in history.
+//! It also inserts an `AuthoritativeDespawn`, which stops rollbacks undoing the despawn.
+//!
+//! Children of a despawning entity get a copy of its `DespawnMarker`, so their registered
+//! components are removed and revived along with the parent. To also rollback re-parenting,
+//! call `app.register_rollback_hierarchy()`, which records each entity's `Parent` in a
+//! `ParentHistory`. See the `hierarchy` test.
+//!
 //! The `despawn_revival_during_rollback` test
 //! does something similar, but triggers a rollback which will restore components to an entity
-//! tagged with a `DespawnMarker` in order to resimulate after a server update arrives, and then
-//! remove the components again.
+//! tagged with an authoritative `DespawnMarker` in order to resimulate after a server update arrives,
+//! and then remove the components again.
 //!
 //! The `component_add_and_remove` test
 //! tests how a server can add a component to an entity in the past, in this case a Shield, which
@@ -185,7 +351,11 @@ pub(crate) mod components;
 mod error;
 mod frame_buffer;
 mod game_clock;
+mod query;
+pub(crate) mod registry;
 pub(crate) mod resources;
+mod rewind;
+mod spawning;
 pub(crate) mod systems;
 mod traits;
 
@@ -194,7 +364,13 @@ pub mod prelude {
     pub use crate::error::*;
     pub use crate::frame_buffer::*;
     pub use crate::game_clock::*;
+    pub use crate::query::*;
+    pub use crate::registry::{
+        RegisteredType, RegistrationKind, TimewarpRegistry, TypeMemoryUsage,
+    };
     pub use crate::resources::*;
+    pub use crate::rewind::*;
+    pub use crate::spawning::*;
     pub use crate::traits::*;
     pub use crate::TimewarpPlugin;
     pub type FrameNumber = u32;
@@ -244,7 +420,13 @@ impl Plugin for TimewarpPlugin {
         app.insert_resource(self.config.clone())
             // RollbackRequest events are drained manually in `consolidate_rollback_requests`
             .init_resource::<Events<RollbackRequest>>()
+            .init_resource::<registry::TimewarpRegistry>()
             .insert_resource(RollbackStats::new(192)) // 3 seconds at 64hz
+            .init_resource::<ConfirmedFrame>()
+            .init_resource::<SnapshotBatches>()
+            .init_resource::<KeyedSpawns>()
+            .add_event::<BlueprintMispredicted>()
+            .add_event::<TimewarpCommandError>()
             //
             // PREFIX
             //
@@ -327,29 +509,6 @@ impl Plugin for TimewarpPlugin {
                 self.config.schedule(),
                 systems::sanity_check.in_set(TimewarpPrefixSet::First),
             )
-            .add_systems(
-                self.config.schedule(),
-                (
-                    systems::prefix_in_rollback::check_for_rollback_completion,
-                    apply_deferred,
-                )
-                    .chain()
-                    .in_set(TimewarpPrefixSet::InRollback),
-            )
-            .add_systems(
-                self.config.schedule(),
-                (
-                    systems::prefix_not_in_rollback::consolidate_rollback_requests,
-                    apply_deferred,
-                )
-                    .chain()
-                    .in_set(TimewarpPrefixSet::NotInRollback),
-            )
-            .add_systems(
-                self.config.schedule(),
-                systems::prefix_start_rollback::rollback_initiated
-                    .in_set(TimewarpPrefixSet::StartRollback),
-            )
             .add_systems(
                 self.config.schedule(),
                 apply_deferred.in_set(TimewarpPrefixSet::Last),
@@ -367,9 +526,22 @@ impl Plugin for TimewarpPlugin {
                 )
                     .chain(),
             )
+            // children need their DespawnMarkers before the Components set removes components
+            .add_systems(
+                self.config.schedule(),
+                (
+                    systems::postfix_components::propagate_despawn_markers_to_children,
+                    apply_deferred,
+                )
+                    .chain()
+                    .in_set(TimewarpPostfixSet::First),
+            )
             .add_systems(
                 self.config.schedule(),
-                systems::postfix_last::despawn_entities_with_elapsed_despawn_marker
+                (
+                    systems::postfix_last::despawn_entities_with_elapsed_despawn_marker,
+                    systems::postfix_last::prune_keyed_spawns,
+                )
                     .in_set(TimewarpPostfixSet::Last),
             )
             // flush commands at the very end, since they may be referencing entities which
@@ -391,6 +563,132 @@ impl Plugin for TimewarpPlugin {
             // inserted by bevy::log::TimePlugin
             .insert_resource(Time::<Fixed>::from_seconds(1.0 / 64.0))
             .insert_resource(GameClock::new());
+
+        assert!(
+            self.config.late_input_window() < self.config.rollback_window(),
+            "late_input_window must be smaller than rollback_window"
+        );
+        assert!(
+            self.config.late_input_window() == 0
+                || matches!(
+                    self.config.consolidation_strategy(),
+                    RollbackConsolidationStrategy::Oldest
+                ),
+            "late_input_window requires RollbackConsolidationStrategy::Oldest"
+        );
+        assert!(
+            !self.config.is_peer_to_peer()
+                || matches!(
+                    self.config.consolidation_strategy(),
+                    RollbackConsolidationStrategy::Oldest
+                ),
+            "TimewarpMode::PeerToPeer requires RollbackConsolidationStrategy::Oldest"
+        );
+        if self.config.receives_snapshots() {
+            // must happen before snapshots are compared with the adopted history
+            app.add_systems(
+                self.config.schedule(),
+                systems::prefix_first::match_predicted_spawns
+                    .run_if(not(resource_exists::<Rollback>))
+                    .in_set(TimewarpPrefixSet::First),
+            );
+        }
+        if self.config.is_lockstep() {
+            app.init_resource::<LockstepStats>()
+                .add_systems(
+                    self.config.schedule(),
+                    systems::prefix_first::update_lockstep_stats.before(TimewarpPrefixSet::First),
+                )
+                .configure_sets(
+                    self.config.schedule(),
+                    (
+                        TimewarpPrefixSet::First,
+                        TimewarpPrefixSet::InRollback,
+                        TimewarpPrefixSet::NotInRollback,
+                        TimewarpPrefixSet::StartRollback,
+                        TimewarpPrefixSet::UnwrapBlueprints,
+                        TimewarpPrefixSet::Last,
+                        TimewarpPostfixSet::First,
+                        TimewarpPostfixSet::Components,
+                        TimewarpPostfixSet::InRollback,
+                        TimewarpPostfixSet::Last,
+                    )
+                        .run_if(lockstep_ready),
+                )
+                .configure_sets(
+                    self.config.schedule(),
+                    self.config.first_set().run_if(lockstep_ready),
+                )
+                .configure_sets(
+                    self.config.schedule(),
+                    self.config.last_set().run_if(lockstep_ready),
+                );
+        }
+        // components registered at runtime share one set of systems. registration can happen
+        // after startup, so they are always added, and skipped until something is registered.
+        app.add_systems(
+            self.config.schedule(),
+            systems::postfix_components::record_dynamic_component_history
+                .run_if(registry::dynamic_components_registered)
+                .in_set(TimewarpPostfixSet::Components),
+        );
+        if self.config.rollback_enabled() && self.config.receives_snapshots() {
+            app.add_systems(
+                self.config.schedule(),
+                systems::prefix_not_in_rollback::apply_dynamic_snapshots_and_maybe_rollback
+                    .run_if(registry::dynamic_components_registered)
+                    .before(systems::prefix_not_in_rollback::consolidate_rollback_requests)
+                    .in_set(TimewarpPrefixSet::NotInRollback),
+            );
+        }
+        if !self.config.rollback_enabled() {
+            // servers and lockstep games don't rollback, they just record history.
+            app.add_systems(
+                self.config.schedule(),
+                systems::prefix_not_in_rollback::discard_rollback_requests
+                    .in_set(TimewarpPrefixSet::NotInRollback),
+            );
+        } else {
+            app.add_systems(
+                self.config.schedule(),
+                (
+                    systems::prefix_in_rollback::despawn_mispredicted_blueprints,
+                    systems::prefix_in_rollback::despawn_keyed_spawns_not_respawned,
+                    systems::prefix_in_rollback::check_for_rollback_completion,
+                    apply_deferred,
+                )
+                    .chain()
+                    .in_set(TimewarpPrefixSet::InRollback),
+            )
+            .add_systems(
+                self.config.schedule(),
+                (
+                    systems::prefix_not_in_rollback::consolidate_rollback_requests,
+                    apply_deferred,
+                )
+                    .chain()
+                    .in_set(TimewarpPrefixSet::NotInRollback),
+            )
+            .add_systems(
+                self.config.schedule(),
+                (
+                    systems::prefix_start_rollback::rollback_initiated,
+                    systems::prefix_start_rollback::undo_predicted_despawns,
+                    systems::prefix_start_rollback::unspawn_keyed_spawns,
+                    systems::prefix_start_rollback::unassemble_predicted_blueprints,
+                    systems::prefix_start_rollback::unassemble_blueprints,
+                )
+                    .chain()
+                    .in_set(TimewarpPrefixSet::StartRollback),
+            )
+            .add_systems(
+                self.config.schedule(),
+                systems::prefix_start_rollback::rollback_dynamic_components
+                    .run_if(registry::dynamic_components_registered)
+                    .in_set(TimewarpPrefixSet::StartRollback)
+                    .after(systems::prefix_start_rollback::unassemble_blueprints),
+            );
+        }
     }
 }
 
@@ -0,0 +1,51 @@
+use crate::prelude::*;
+use bevy::{
+    ecs::{
+        query::{QueryEntityError, QueryFilter},
+        system::SystemParam,
+    },
+    prelude::*,
+};
+
+/// Read-only access to the buffered values of a timewarp-registered component at past frames.
+///
+/// Yields `(Entity, Option<&T>)` for every entity with a [`ComponentHistory<T>`]. The value is
+/// `None` if the component wasn't alive at the requested frame (not yet born, or removed),
+/// or if the frame is outside of the buffered range.
+///
+/// ```rust,ignore
+/// fn check_hit(hitboxes: HistoricalQuery<Hitbox, With<Player>>, shot: Res<Shot>) {
+///     for (entity, opt_hitbox) in hitboxes.iter_at(shot.frame) {
+///         // ...
+///     }
+/// }
+/// ```
+#[derive(SystemParam)]
+pub struct HistoricalQuery<'w, 's, T: TimewarpComponent, F: QueryFilter + 'static = ()> {
+    query: Query<'w, 's, (Entity, &'static ComponentHistory<T>), (F, Without<NoRollback>)>,
+}
+
+impl<'w, 's, T: TimewarpComponent, F: QueryFilter + 'static> HistoricalQuery<'w, 's, T, F> {
+    /// iterate over all matching entities, with the value of `T` they had at `frame`.
+    pub fn iter_at(&self, frame: FrameNumber) -> impl Iterator<Item = (Entity, Option<&T>)> + '_ {
+        self.query
+            .iter()
+            .map(move |(entity, ch)| (entity, ch.alive_value_at_frame(frame)))
+    }
+
+    /// the value of `T` for `entity` at `frame`.
+    /// Errors if the entity doesn't match the query, ie it has no [`ComponentHistory<T>`].
+    pub fn get_at(
+        &self,
+        entity: Entity,
+        frame: FrameNumber,
+    ) -> Result<Option<&T>, QueryEntityError> {
+        let (_, ch) = self.query.get(entity)?;
+        Ok(ch.alive_value_at_frame(frame))
+    }
+
+    /// true if `entity` has a buffered, alive value of `T` at `frame`.
+    pub fn contains_at(&self, entity: Entity, frame: FrameNumber) -> bool {
+        matches!(self.get_at(entity, frame), Ok(Some(_)))
+    }
+}
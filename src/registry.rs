@@ -0,0 +1,512 @@
+use crate::prelude::*;
+use crate::rewind::{rewind_component, RestoreFn};
+use bevy::prelude::*;
+use std::any::TypeId;
+
+/// Type-erased functions for a rollback-registered component, so we can operate on every
+/// registered component type from places where we only have a `&mut World`.
+pub(crate) struct RegisteredComponent {
+    pub(crate) type_id: TypeId,
+    pub(crate) rewind:
+        fn(&mut World, FrameNumber, &RewindFilter, &mut RewoundEntities) -> RestoreFn,
+    /// marks the component dead at a frame <= the current frame
+    pub(crate) kill_at_frame: fn(&mut EntityWorldMut, FrameNumber),
+    /// undoes a death at a frame, for predicted despawns that were rolled back
+    pub(crate) undo_death_at_frame: fn(&mut EntityWorldMut, FrameNumber),
+    /// removes the component from a predicted entity the rollback unspawns, see `unspawn_keyed_spawns`
+    pub(crate) unspawn_at_frame: fn(&mut EntityWorldMut, FrameNumber),
+    /// moves the component and its history from a predicted entity to its server entity
+    pub(crate) adopt: fn(&mut World, Entity, Entity),
+    /// moves the component into a new history born at a past frame, see `spawn_at_frame`
+    pub(crate) spawn_at_frame: fn(&mut EntityWorldMut, FrameNumber) -> bool,
+    pub(crate) memory_usage: fn(&mut World) -> TypeMemoryUsage,
+}
+
+impl RegisteredComponent {
+    pub(crate) fn new<T: TimewarpComponent>() -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            rewind: rewind_component::<T>,
+            kill_at_frame: kill_component_at_frame::<T>,
+            undo_death_at_frame: undo_component_death_at_frame::<T>,
+            unspawn_at_frame: unspawn_component_at_frame::<T>,
+            adopt: adopt_component::<T>,
+            spawn_at_frame: spawn_component_at_frame::<T>,
+            memory_usage: component_memory_usage::<T>,
+        }
+    }
+}
+
+/// Records that T died at `frame`, in the CH and as a tombstone in the SS, so rollbacks respect
+/// it. If `frame` is the current frame, we remove T now, otherwise the rollback will.
+fn kill_component_at_frame<T: TimewarpComponent>(entity: &mut EntityWorldMut, frame: FrameNumber) {
+    let Some(mut ch) = entity.get_mut::<ComponentHistory<T>>() else {
+        return;
+    };
+    if !ch.alive_at_frame(frame) {
+        return;
+    }
+    ch.report_death_at_frame(frame);
+    if let Some(mut ss) = entity.get_mut::<ServerSnapshot<T>>() {
+        ss.insert_removal(frame);
+    }
+    let current_frame = entity.world().resource::<GameClock>().frame();
+    if frame == current_frame {
+        entity.remove::<T>();
+    }
+}
+
+fn undo_component_death_at_frame<T: TimewarpComponent>(
+    entity: &mut EntityWorldMut,
+    frame: FrameNumber,
+) {
+    if let Some(mut ch) = entity.get_mut::<ComponentHistory<T>>() {
+        ch.undo_death_at_frame(frame);
+    }
+}
+
+/// Like `kill_component_at_frame`, but for predictions a rollback is undoing: T is removed now,
+/// and the history forgets T was alive from `frame`, so it isn't reinserted during the
+/// resimulation unless the game logic inserts it again. There's no tombstone in the SS, since
+/// the server didn't remove T, and a tombstone would trigger another rollback if the
+/// resimulation inserts it again.
+///
+/// If T wasn't alive before `frame`, the CH only holds the predictions we are undoing, so it is
+/// removed too, and a new one is added if the resimulation inserts T again.
+fn unspawn_component_at_frame<T: TimewarpComponent>(
+    entity: &mut EntityWorldMut,
+    frame: FrameNumber,
+) {
+    let Some(mut ch) = entity.get_mut::<ComponentHistory<T>>() else {
+        entity.remove::<T>();
+        return;
+    };
+    ch.forget_lives_from(frame);
+    if ch.alive_ranges.is_empty() {
+        entity.remove::<(T, ComponentHistory<T>)>();
+    } else {
+        entity.remove::<T>();
+    }
+}
+
+/// Moves T, its ComponentHistory and ServerSnapshot from `from` to `to`, unless `to` already has
+/// its own history, which is newer information from the server.
+fn adopt_component<T: TimewarpComponent>(world: &mut World, from: Entity, to: Entity) {
+    let Some(mut from_mut) = world.get_entity_mut(from) else {
+        return;
+    };
+    let comp = from_mut.take::<T>();
+    let ch = from_mut.take::<ComponentHistory<T>>();
+    let ss = from_mut.take::<ServerSnapshot<T>>();
+    let Some(mut to_mut) = world.get_entity_mut(to) else {
+        return;
+    };
+    if to_mut.contains::<ComponentHistory<T>>() {
+        info!(
+            "{to:?} already has a {:?} history, dropping the predicted history from {from:?}",
+            std::any::type_name::<T>()
+        );
+        return;
+    }
+    let Some(ch) = ch else {
+        return;
+    };
+    trace!(
+        "{to:?} adopting {:?} history from {from:?}",
+        std::any::type_name::<T>()
+    );
+    to_mut.insert(ch);
+    if let Some(comp) = comp.filter(|_| !to_mut.contains::<T>()) {
+        to_mut.insert(comp);
+    }
+    if let Some(ss) = ss.filter(|_| !to_mut.contains::<ServerSnapshot<T>>()) {
+        to_mut.insert(ss);
+    }
+}
+
+/// Gives an entity spawned with T a ComponentHistory and ServerSnapshot born at `frame`, with
+/// T's value. The rollback restores T from them, so T stays on the entity (taking it would be
+/// reported as a death). Returns false if the entity doesn't have T, or already has its history.
+fn spawn_component_at_frame<T: TimewarpComponent>(
+    entity: &mut EntityWorldMut,
+    frame: FrameNumber,
+) -> bool {
+    if entity.contains::<ComponentHistory<T>>() {
+        return false;
+    }
+    let Some(comp) = entity.get::<T>().cloned() else {
+        return false;
+    };
+    entity
+        .insert_component_at_frame(frame, &comp)
+        .expect("spawn_at_frame checks the frame is in the rollback window");
+    true
+}
+
+/// Sums the capacity of every `ComponentHistory<T>` and `ServerSnapshot<T>`
+fn component_memory_usage<T: TimewarpComponent>(world: &mut World) -> TypeMemoryUsage {
+    let mut usage = TypeMemoryUsage::new(std::any::type_name::<T>());
+    let slot = std::mem::size_of::<Option<T>>();
+    let mut q = world.query_filtered::<
+        (Option<&ComponentHistory<T>>, Option<&ServerSnapshot<T>>),
+        Or<(With<ComponentHistory<T>>, With<ServerSnapshot<T>>)>,
+    >();
+    for (ch, ss) in q.iter(world) {
+        usage.entities += 1;
+        if let Some(ch) = ch {
+            usage.bytes += ch.values.capacity() * slot
+                + ch.alive_ranges.capacity() * std::mem::size_of::<FrameRange>();
+        }
+        if let Some(ss) = ss {
+            usage.bytes += ss.values.capacity() * slot
+                + ss.removals.capacity() * std::mem::size_of::<FrameNumber>();
+        }
+    }
+    usage
+}
+
+/// Sums the `DynamicComponentHistory` and `DynamicServerSnapshot` buffers for one type,
+/// including the boxed values.
+fn dynamic_component_memory_usage(
+    world: &mut World,
+    reg: &DynamicRegisteredComponent,
+) -> TypeMemoryUsage {
+    fn buffer_bytes<T: Clone + Send + Sync + PartialEq + std::fmt::Debug>(
+        buffer: &FrameBuffer<T>,
+        value_bytes: impl Fn(&T) -> usize,
+    ) -> usize {
+        buffer.capacity() * std::mem::size_of::<Option<T>>()
+            + buffer
+                .current_range()
+                .filter_map(|frame| buffer.get(frame))
+                .map(value_bytes)
+                .sum::<usize>()
+    }
+    let boxed = |value: &ReflectValue| std::mem::size_of_val(value.0.as_ref());
+    let mut usage = TypeMemoryUsage::new(reg.type_path);
+    let mut q = world.query::<(
+        Option<&DynamicComponentHistory>,
+        Option<&DynamicServerSnapshot>,
+    )>();
+    for (dch, dss) in q.iter(world) {
+        let history = dch.and_then(|dch| dch.values.get(&reg.type_id));
+        let snapshots = dss.and_then(|dss| dss.values.get(&reg.type_id));
+        if history.is_none() && snapshots.is_none() {
+            continue;
+        }
+        usage.entities += 1;
+        if let Some(history) = history {
+            usage.bytes += buffer_bytes(history, |value| value.as_ref().map_or(0, boxed));
+        }
+        if let Some(snapshots) = snapshots {
+            usage.bytes += buffer_bytes(snapshots, boxed);
+        }
+    }
+    usage
+}
+
+/// A component registered for rollback at runtime, by reflection. Its history is type-erased,
+/// in a [`DynamicComponentHistory`].
+#[derive(Clone)]
+pub(crate) struct DynamicRegisteredComponent {
+    pub(crate) type_id: TypeId,
+    pub(crate) type_path: &'static str,
+    pub(crate) reflect: ReflectComponent,
+}
+
+impl DynamicRegisteredComponent {
+    /// Like `kill_component_at_frame`, records that the component died at `frame`, and removes
+    /// it now if that's the current frame. A resimulation removes it again from the
+    /// [`DespawnMarker`] frame on, see `record_dynamic_component_history`.
+    pub(crate) fn kill_at_frame(&self, entity: &mut EntityWorldMut, frame: FrameNumber) {
+        let current_frame = entity.world().resource::<GameClock>().frame();
+        if frame == current_frame {
+            self.reflect.remove(entity);
+        }
+        let id = entity.id();
+        let Some(mut dch) = entity.get_mut::<DynamicComponentHistory>() else {
+            return;
+        };
+        if dch.value_at_frame(self.type_id, frame).is_none() {
+            return;
+        }
+        if let Err(err) = dch.insert(self.type_id, self.type_path, frame, None) {
+            error!(
+                "{id:?} Couldn't record death of {} @ {frame}: {err:?}",
+                self.type_path
+            );
+        }
+    }
+
+    /// Like `unspawn_component_at_frame`, removes the component from a predicted entity the
+    /// rollback unspawns. If it wasn't alive before `frame`, its history only holds the
+    /// predictions we are undoing, so that goes too.
+    pub(crate) fn unspawn_at_frame(&self, entity: &mut EntityWorldMut, frame: FrameNumber) {
+        self.reflect.remove(entity);
+        let Some(mut dch) = entity.get_mut::<DynamicComponentHistory>() else {
+            return;
+        };
+        let Some(values) = dch.values.get(&self.type_id) else {
+            return;
+        };
+        let alive_before = values
+            .current_range()
+            .filter(|f| *f < frame)
+            .any(|f| values.get(f).is_some_and(|value| value.is_some()));
+        if !alive_before {
+            dch.values.remove(&self.type_id);
+        }
+    }
+
+    /// Like `adopt_component`, moves the component and its history and snapshots from `from` to
+    /// `to`, unless `to` already has its own history of it.
+    pub(crate) fn adopt(&self, world: &mut World, from: Entity, to: Entity) {
+        let Some(mut from_mut) = world.get_entity_mut(from) else {
+            return;
+        };
+        let comp = self
+            .reflect
+            .reflect(EntityRef::from(&from_mut))
+            .map(|value| value.clone_value());
+        self.reflect.remove(&mut from_mut);
+        let history = from_mut
+            .get_mut::<DynamicComponentHistory>()
+            .and_then(|mut dch| dch.values.remove(&self.type_id));
+        let snapshots = from_mut
+            .get_mut::<DynamicServerSnapshot>()
+            .and_then(|mut dss| dss.values.remove(&self.type_id));
+        let type_registry = world.resource::<AppTypeRegistry>().clone();
+        let Some(mut to_mut) = world.get_entity_mut(to) else {
+            return;
+        };
+        if has_dynamic_history(&to_mut, self.type_id) {
+            info!(
+                "{to:?} already has a {:?} history, dropping the predicted history from {from:?}",
+                self.type_path
+            );
+            return;
+        }
+        let Some(history) = history else {
+            return;
+        };
+        trace!("{to:?} adopting {:?} history from {from:?}", self.type_path);
+        add_dynamic_history(&mut to_mut, history.newest_frame());
+        to_mut
+            .get_mut::<DynamicComponentHistory>()
+            .expect("Just inserted")
+            .values
+            .insert(self.type_id, history);
+        if let Some(comp) = comp.filter(|_| !to_mut.contains_type_id(self.type_id)) {
+            self.reflect
+                .insert(&mut to_mut, comp.as_ref(), &type_registry.read());
+        }
+        if let Some(snapshots) = snapshots {
+            to_mut
+                .get_mut::<DynamicServerSnapshot>()
+                .expect("Just inserted")
+                .values
+                .entry(self.type_id)
+                .or_insert(snapshots);
+        }
+    }
+
+    /// Like `spawn_component_at_frame`, gives an entity spawned with the component a history
+    /// born at `frame`. Returns false if the entity doesn't have it, or already has its history.
+    pub(crate) fn spawn_at_frame(&self, entity: &mut EntityWorldMut, frame: FrameNumber) -> bool {
+        if has_dynamic_history(entity, self.type_id) {
+            return false;
+        }
+        let Some(value) = self
+            .reflect
+            .reflect(EntityRef::from(&*entity))
+            .map(|value| ReflectValue(value.clone_value()))
+        else {
+            return false;
+        };
+        add_dynamic_history(entity, frame);
+        entity
+            .get_mut::<DynamicComponentHistory>()
+            .expect("Just inserted")
+            .insert(self.type_id, self.type_path, frame, Some(value))
+            .expect("spawn_at_frame checks the frame is in the rollback window");
+        true
+    }
+}
+
+fn has_dynamic_history(entity: &EntityWorldMut, type_id: TypeId) -> bool {
+    entity
+        .get::<DynamicComponentHistory>()
+        .is_some_and(|dch| dch.values.contains_key(&type_id))
+}
+
+/// Adds the [`DynamicComponentHistory`], [`DynamicServerSnapshot`] and [`TimewarpStatus`] an
+/// entity with components registered at runtime needs, if it doesn't have them yet.
+pub(crate) fn add_dynamic_history(entity: &mut EntityWorldMut, frame: FrameNumber) {
+    let window_size = entity
+        .world()
+        .resource::<TimewarpConfig>()
+        .rollback_window() as usize;
+    if !entity.contains::<DynamicComponentHistory>() {
+        entity.insert(DynamicComponentHistory::with_capacity(window_size));
+    }
+    if !entity.contains::<DynamicServerSnapshot>() {
+        entity.insert(DynamicServerSnapshot::with_capacity(window_size * 60));
+    }
+    if !entity.contains::<TimewarpStatus>() {
+        entity.insert(TimewarpStatus::new(frame));
+    }
+}
+
+/// How a type was registered with timewarp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationKind {
+    /// `register_rollback*` or `register_rollback_bundle`
+    Rollback,
+    /// `register_rollback_by_type_id` or `register_rollback_by_type_path`
+    DynamicRollback,
+    /// `register_blueprint`
+    Blueprint,
+    /// `register_blueprint_with_assembler`
+    BlueprintWithAssembler,
+}
+
+impl RegistrationKind {
+    /// registering a type as both would add two sets of systems doing the same job
+    fn overlaps(self, other: RegistrationKind) -> bool {
+        use RegistrationKind::*;
+        matches!(
+            (self, other),
+            (Rollback | DynamicRollback, Rollback | DynamicRollback)
+                | (
+                    Blueprint | BlueprintWithAssembler,
+                    Blueprint | BlueprintWithAssembler
+                )
+        )
+    }
+}
+
+/// A type registered with timewarp, and the options it was registered with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisteredType {
+    pub type_id: TypeId,
+    pub type_name: &'static str,
+    pub kind: RegistrationKind,
+    pub correction_logging: bool,
+    pub snap_only: bool,
+}
+
+impl RegisteredType {
+    pub(crate) fn new(type_id: TypeId, type_name: &'static str, kind: RegistrationKind) -> Self {
+        Self {
+            type_id,
+            type_name,
+            kind,
+            correction_logging: false,
+            snap_only: false,
+        }
+    }
+}
+
+/// Approximate memory used by the history and snapshot buffers of one rollback-registered type,
+/// across all entities.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeMemoryUsage {
+    pub type_name: &'static str,
+    /// entities with a history or snapshot buffer for this type
+    pub entities: usize,
+    pub bytes: usize,
+}
+
+impl TypeMemoryUsage {
+    fn new(type_name: &'static str) -> Self {
+        Self {
+            type_name,
+            entities: 0,
+            bytes: 0,
+        }
+    }
+}
+
+/// Every type registered with timewarp, populated by `register_rollback*` and
+/// `register_blueprint*`.
+#[derive(Resource, Default)]
+pub struct TimewarpRegistry {
+    types: Vec<RegisteredType>,
+    pub(crate) components: Vec<RegisteredComponent>,
+    /// for blueprints registered with an assembler, undoes assemblies from this frame onwards
+    pub(crate) unassemblers: Vec<fn(&mut World, FrameNumber)>,
+    /// for every blueprint, removes it and anything its assembler added from a predicted entity
+    pub(crate) predicted_unassemblers: Vec<fn(&mut World, Entity)>,
+    /// registered with `register_rollback_by_type_id` or `register_rollback_by_type_path`
+    pub(crate) dynamic_components: Vec<DynamicRegisteredComponent>,
+}
+
+/// run condition for the systems shared by every component registered at runtime
+pub(crate) fn dynamic_components_registered(registry: Res<TimewarpRegistry>) -> bool {
+    !registry.dynamic_components.is_empty()
+}
+
+impl TimewarpRegistry {
+    /// every registration, in the order they happened
+    pub fn types(&self) -> &[RegisteredType] {
+        &self.types
+    }
+    pub fn get(&self, type_id: TypeId, kind: RegistrationKind) -> Option<&RegisteredType> {
+        self.types
+            .iter()
+            .find(|reg| reg.type_id == type_id && reg.kind == kind)
+    }
+    pub fn is_registered<T: 'static>(&self, kind: RegistrationKind) -> bool {
+        self.get(TypeId::of::<T>(), kind).is_some()
+    }
+    /// Memory used by the history and snapshot buffers of each rollback-registered type.
+    /// Walks every entity, so this is for debugging and diagnostics, not every frame.
+    pub fn memory_usage(world: &mut World) -> Vec<TypeMemoryUsage> {
+        let registry = world.resource::<TimewarpRegistry>();
+        let usage_fns = registry
+            .components
+            .iter()
+            .map(|reg| reg.memory_usage)
+            .collect::<Vec<_>>();
+        let dynamic = registry.dynamic_components.clone();
+        let mut usage = usage_fns
+            .into_iter()
+            .map(|memory_usage| memory_usage(world))
+            .collect::<Vec<_>>();
+        usage.extend(
+            dynamic
+                .iter()
+                .map(|reg| dynamic_component_memory_usage(world, reg)),
+        );
+        usage
+    }
+    /// Records a registration. Returns false, and warns, if this type was already registered
+    /// like this, or as the static/dynamic or assembler/plain counterpart, in which case the
+    /// caller mustn't add its systems again.
+    pub(crate) fn add(&mut self, registered: RegisteredType) -> bool {
+        let existing = self
+            .types
+            .iter()
+            .find(|reg| reg.type_id == registered.type_id && reg.kind.overlaps(registered.kind));
+        if let Some(existing) = existing {
+            if *existing == registered {
+                warn!(
+                    "{} is already registered as {:?}, ignoring the duplicate registration",
+                    existing.type_name, existing.kind
+                );
+            } else {
+                warn!(
+                    "{} is already registered as {existing:?}, ignoring the registration as \
+                     {registered:?}, the first registration's options are kept",
+                    existing.type_name
+                );
+            }
+            return false;
+        }
+        self.types.push(registered);
+        true
+    }
+    pub(crate) fn register<T: TimewarpComponent>(&mut self) {
+        self.components.push(RegisteredComponent::new::<T>());
+    }
+}
@@ -1,22 +1,47 @@
-use crate::FrameNumber;
+use crate::{components::SpawnKey, error::TimewarpError, FrameNumber};
 use bevy::{
     ecs::schedule::{InternedScheduleLabel, ScheduleLabel},
     prelude::*,
-    utils::intern::Interned,
+    utils::{intern::Interned, HashMap},
 };
-use std::{collections::VecDeque, ops::Range, time::Duration};
+use std::{any::TypeId, collections::VecDeque, ops::Range, time::Duration};
 
 /// if various systems request rollbacks to different frames within one tick, when consolidating
 /// those requests into an actionable Rollback, do we choose the oldest or newest frame from the
 /// list of requests?
+///
+/// Not used for ticks where [`SnapshotBatches`] were received, since then we know which frame
+/// to choose.
 #[derive(Debug, Copy, Clone)]
 pub enum RollbackConsolidationStrategy {
     Oldest,
     Newest,
 }
 
+/// Which side of the network timewarp is running on, which determines which systems we add.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TimewarpMode {
+    /// Predicts ahead of the server, and rolls back when server snapshots disagree with
+    /// our predictions.
+    #[default]
+    Client,
+    /// Only records component history, eg for lag compensation and snapshot baselines.
+    /// No `ServerSnapshot`s are added, and we never rollback – unless a `late_input_window` is
+    /// configured, in which case inputs arriving a few frames late trigger a rollback.
+    Server,
+    /// No authoritative server: every peer simulates the whole game, and confirmed remote inputs
+    /// are the only authority. Rollbacks are triggered when a confirmed input differs from the
+    /// one we predicted, rather than by `ServerSnapshot`s. See [`ConfirmedFrame`].
+    PeerToPeer,
+    /// Never predicts or rolls back: the simulation stalls until every player's input for the
+    /// next frame has arrived. See [`LockstepStats`] and [`lockstep_ready`].
+    Lockstep,
+}
+
 #[derive(Resource, Debug, Clone)]
 pub struct TimewarpConfig {
+    /// client or server. defaults to client.
+    pub mode: TimewarpMode,
     /// if you can update some entities one frame and some another, ie you don't receive
     /// entire-world update, set this to Oldest, or you will miss data.
     /// the default is Newest (for replicon, which is entire-world updates only atm)
@@ -28,12 +53,20 @@ pub struct TimewarpConfig {
     /// the stored predicted value matches the server snapshot.
     /// meant as a worst-case scenario for checking performance really.
     pub force_rollback_always: bool,
+    /// servers only: how many frames late a player input can arrive and still be accepted,
+    /// triggering a rollback and resimulation. 0 means late inputs are never accepted.
+    pub late_input_window: FrameNumber,
     /// schedule in which our `after_set` and rollback systems run, defaults to FixedUpdate
     pub schedule: InternedScheduleLabel,
     /// first set containing game logic
     pub first_set: Interned<dyn SystemSet>,
     /// last set containing game logic
     pub last_set: Interned<dyn SystemSet>,
+    /// clients only: how many frames a [`PredictedSpawn`] waits for its server entity before
+    /// we give up and despawn it. defaults to the rollback window.
+    ///
+    /// [`PredictedSpawn`]: crate::prelude::PredictedSpawn
+    pub predicted_spawn_timeout: Option<FrameNumber>,
 }
 
 impl TimewarpConfig {
@@ -41,17 +74,27 @@ impl TimewarpConfig {
     /// rollback_window: 30
     /// forced_rollback: false
     /// schedule: FixedUpdate
+    /// mode: Client
     pub fn new(first_set: impl SystemSet, last_set: impl SystemSet) -> Self {
         Self {
+            mode: TimewarpMode::Client,
             consolidation_strategy: RollbackConsolidationStrategy::Newest,
             first_set: first_set.intern(),
             last_set: last_set.intern(),
             // and defaults, override with builder fns:
             rollback_window: 30,
             force_rollback_always: false,
+            late_input_window: 0,
             schedule: FixedUpdate.intern(),
+            predicted_spawn_timeout: None,
         }
     }
+    /// Peers send inputs for different frames, so `PeerToPeer` requires the `Oldest`
+    /// consolidation strategy, which `TimewarpPlugin` asserts.
+    pub fn with_mode(mut self, mode: TimewarpMode) -> Self {
+        self.mode = mode;
+        self
+    }
     pub fn with_schedule(mut self, schedule: impl ScheduleLabel) -> Self {
         self.schedule = schedule.intern();
         self
@@ -64,11 +107,62 @@ impl TimewarpConfig {
         self.rollback_window = num_frames;
         self
     }
+    /// servers only: accept inputs arriving up to `num_frames` late, and resimulate.
+    /// Late inputs for different players can arrive for different frames in the same tick,
+    /// so this requires the `Oldest` consolidation strategy, which `TimewarpPlugin` asserts.
+    pub fn with_late_input_window(mut self, num_frames: FrameNumber) -> Self {
+        self.late_input_window = num_frames;
+        self
+    }
+    pub fn with_predicted_spawn_timeout(mut self, num_frames: FrameNumber) -> Self {
+        self.predicted_spawn_timeout = Some(num_frames);
+        self
+    }
     pub fn with_consolidation_strategy(mut self, strategy: RollbackConsolidationStrategy) -> Self {
         self.consolidation_strategy = strategy;
         self
     }
 
+    pub fn mode(&self) -> TimewarpMode {
+        self.mode
+    }
+    pub fn is_server(&self) -> bool {
+        self.mode == TimewarpMode::Server
+    }
+    pub fn is_peer_to_peer(&self) -> bool {
+        self.mode == TimewarpMode::PeerToPeer
+    }
+    pub fn is_lockstep(&self) -> bool {
+        self.mode == TimewarpMode::Lockstep
+    }
+    /// only clients receive `ServerSnapshot`s from an authoritative server.
+    pub fn receives_snapshots(&self) -> bool {
+        self.mode == TimewarpMode::Client
+    }
+    /// false for lockstep, and for servers that only record history.
+    pub fn rollback_enabled(&self) -> bool {
+        match self.mode {
+            TimewarpMode::Client | TimewarpMode::PeerToPeer => true,
+            TimewarpMode::Server => self.late_input_window > 0,
+            TimewarpMode::Lockstep => false,
+        }
+    }
+    pub fn late_input_window(&self) -> FrameNumber {
+        self.late_input_window
+    }
+    /// How many frames late an input can arrive and still trigger a rollback.
+    /// Inputs for the next frame to be simulated are 0 frames late.
+    pub fn input_acceptance_window(&self) -> FrameNumber {
+        match self.mode {
+            // we need the CH values for the frame before the input to rollback
+            TimewarpMode::PeerToPeer => self.rollback_window.saturating_sub(1),
+            TimewarpMode::Server => self.late_input_window,
+            // clients are corrected by server snapshots, not by late inputs.
+            TimewarpMode::Client => 0,
+            // we waited for the inputs, so they can't be late.
+            TimewarpMode::Lockstep => 0,
+        }
+    }
     pub fn first_set(&self) -> Interned<dyn SystemSet> {
         self.first_set
     }
@@ -84,6 +178,9 @@ impl TimewarpConfig {
     pub fn rollback_window(&self) -> FrameNumber {
         self.rollback_window
     }
+    pub fn predicted_spawn_timeout(&self) -> FrameNumber {
+        self.predicted_spawn_timeout.unwrap_or(self.rollback_window)
+    }
     pub fn consolidation_strategy(&self) -> RollbackConsolidationStrategy {
         self.consolidation_strategy
     }
@@ -105,6 +202,10 @@ pub struct RollbackStats {
     pub num_rollbacks: u64,
     pub range_faults: u64,
     pub non_rollback_updates: u64,
+    /// inputs that arrived too late to be accepted, see `TimewarpConfig::input_acceptance_window`
+    pub late_inputs_rejected: u64,
+    /// entities with a [`DespawnMarker`] waiting for the actual despawn
+    pub pending_despawns: usize,
     rollback_depths: VecDeque<u8>,
     stat_frames: usize,
 }
@@ -116,6 +217,8 @@ impl RollbackStats {
             num_rollbacks: 0,
             range_faults: 0,
             non_rollback_updates: 0,
+            late_inputs_rejected: 0,
+            pending_despawns: 0,
             rollback_depths: VecDeque::with_capacity(stat_frames),
             stat_frames,
         }
@@ -137,6 +240,117 @@ impl RollbackStats {
     }
 }
 
+/// Lets your networking code say which snapshots arrived together, so timewarp can choose the
+/// right rollback frame without a [`RollbackConsolidationStrategy`].
+///
+/// ```rust,ignore
+/// batches.begin(server_frame);
+/// // .. insert into ServerSnapshots for server_frame ..
+/// batches.end(true); // true if that was every entity's update for server_frame
+/// ```
+///
+/// A complete batch for frame N means entities not mentioned are confirmed unchanged at N, so
+/// rollback requests from snapshots for frames older than N+1 are superseded by it. Other requests,
+/// like late inputs or past-frame commands, are kept. If only partial batches
+/// arrived, we rollback to the oldest requested frame.
+#[derive(Resource, Debug, Default)]
+pub struct SnapshotBatches {
+    /// set once the networking code starts using batches
+    in_use: bool,
+    open: Option<FrameNumber>,
+    /// (frame, complete) for batches ended since we last consolidated rollback requests
+    received: Vec<(FrameNumber, bool)>,
+}
+
+impl SnapshotBatches {
+    /// start a batch of snapshots for `frame`. Ends any batch that is already open, as partial.
+    pub fn begin(&mut self, frame: FrameNumber) {
+        if let Some(open) = self.open {
+            warn!("SnapshotBatch for {open} was never ended, treating as partial");
+            self.received.push((open, false));
+        }
+        self.in_use = true;
+        self.open = Some(frame);
+    }
+    /// end the current batch. `complete` means it contained all updates for its frame.
+    pub fn end(&mut self, complete: bool) {
+        match self.open.take() {
+            Some(frame) => self.received.push((frame, complete)),
+            None => warn!("SnapshotBatches::end called without begin"),
+        }
+    }
+    /// true once any batch has begun. Only then are snapshots for frames after the start of a
+    /// rollback applied while resimulating, since batches can leave us with snapshots for
+    /// different entities at different frames within the rollback range.
+    pub fn in_use(&self) -> bool {
+        self.in_use
+    }
+    /// frame of the batch currently being received, if any
+    pub fn open_frame(&self) -> Option<FrameNumber> {
+        self.open
+    }
+    /// newest frame for which a complete batch was received, since we last consolidated
+    pub fn newest_complete_frame(&self) -> Option<FrameNumber> {
+        self.received
+            .iter()
+            .filter(|(_, complete)| *complete)
+            .map(|(frame, _)| *frame)
+            .max()
+    }
+    /// true if any batches were ended since we last consolidated
+    pub fn received_any(&self) -> bool {
+        !self.received.is_empty()
+    }
+    pub(crate) fn clear_received(&mut self) {
+        self.received.clear();
+    }
+}
+
+/// Run condition for systems that are only needed when the networking code uses
+/// [`SnapshotBatches`].
+pub(crate) fn snapshot_batches_in_use(batches: Res<SnapshotBatches>) -> bool {
+    batches.in_use()
+}
+
+/// Lockstep only: updated at the start of each tick, before any timewarp or game logic sets run.
+#[derive(Resource, Debug, Default)]
+pub struct LockstepStats {
+    /// total ticks where we didn't simulate, because inputs for the next frame were missing
+    pub stalled_ticks: u64,
+    /// consecutive ticks we have currently been stalled for
+    pub current_stall: u32,
+    /// the most consecutive ticks we've been stalled for
+    pub longest_stall: u32,
+    ready: bool,
+}
+
+impl LockstepStats {
+    /// true if all inputs for the next frame have arrived, so we simulate this tick.
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+    pub(crate) fn update(&mut self, ready: bool) {
+        self.ready = ready;
+        if ready {
+            self.current_stall = 0;
+        } else {
+            self.stalled_ticks += 1;
+            self.current_stall += 1;
+            self.longest_stall = self.longest_stall.max(self.current_stall);
+        }
+    }
+}
+
+/// Run condition for lockstep mode. The timewarp sets and your `first_set` and `last_set` only
+/// run when this is true, so `GameClock` stalls until inputs for the next frame are confirmed.
+/// Add it to any other game systems that must not run while stalled.
+pub fn lockstep_ready(stats: Option<Res<LockstepStats>>) -> bool {
+    match stats {
+        Some(stats) => stats.is_ready(),
+        None => true,
+    }
+}
+
 /// If this resource exists, we are doing a rollback. Insert it to initate one manually.
 /// Normally you would never manually insert a Rollback, it would be trigger automatically
 /// in one of the following ways:
@@ -144,6 +358,7 @@ impl RollbackStats {
 /// * You insert a `InsertComponentAtFrame<T>` for a past frame
 /// * You insert a `AssembleBlueprintAtFrame<T>` for a past frame
 /// * You supply ServerSnapshot<T> data for a past frame
+/// * You insert an input into an `InputBuffer<I>` for a past frame
 ///
 #[derive(Resource, Debug, Clone)]
 pub struct Rollback {
@@ -169,21 +384,93 @@ impl Rollback {
     }
 }
 
+/// Peer-to-peer only: the newest frame for which we have confirmed inputs from every peer.
+///
+/// Nothing at or before this frame can be mispredicted, so we never need to rollback further,
+/// and entities despawned by then can be despawned for real.
+/// Updated each frame from the [`InputBuffer`][crate::prelude::InputBuffer]s of registered inputs.
+#[derive(Resource, Debug, Default)]
+pub struct ConfirmedFrame {
+    by_input: HashMap<TypeId, FrameNumber>,
+}
+
+impl ConfirmedFrame {
+    /// None until there are input buffers for a registered input type.
+    pub fn frame(&self) -> Option<FrameNumber> {
+        self.by_input.values().copied().min()
+    }
+    pub(crate) fn set_for_input<I: 'static>(&mut self, frame: Option<FrameNumber>) {
+        match frame {
+            Some(frame) => self.by_input.insert(TypeId::of::<I>(), frame),
+            None => self.by_input.remove(&TypeId::of::<I>()),
+        };
+    }
+}
+
 /// systems that want to initiate a rollback write one of these to
 /// the Events<RollbackRequest> queue.
 #[derive(Event, Debug)]
-pub struct RollbackRequest(FrameNumber);
+pub struct RollbackRequest {
+    frame: FrameNumber,
+    required: bool,
+    from_snapshot: bool,
+}
 
 impl RollbackRequest {
     pub fn resimulate_this_frame_onwards(frame: FrameNumber) -> Self {
         if frame == 0 {
             warn!("RollbackRequest(0)!");
         }
-        Self(frame)
+        Self {
+            frame,
+            required: false,
+            from_snapshot: false,
+        }
+    }
+    /// Like `resimulate_this_frame_onwards`, for requests caused by a `ServerSnapshot`.
+    /// Only these are superseded by a newer complete [`SnapshotBatches`] frame.
+    pub fn from_snapshot(frame: FrameNumber) -> Self {
+        Self {
+            from_snapshot: true,
+            ..Self::resimulate_this_frame_onwards(frame)
+        }
+    }
+    /// Like `resimulate_this_frame_onwards`, but the frame is always resimulated, even if the
+    /// [`RollbackConsolidationStrategy`] or a complete [`SnapshotBatches`] frame would pick a
+    /// newer one. For things that must happen on an exact frame, like blueprint assembly.
+    pub fn must_resimulate_this_frame_onwards(frame: FrameNumber) -> Self {
+        Self {
+            required: true,
+            ..Self::resimulate_this_frame_onwards(frame)
+        }
     }
     pub fn frame(&self) -> FrameNumber {
-        self.0
+        self.frame
+    }
+    pub fn is_required(&self) -> bool {
+        self.required
     }
+    pub fn is_from_snapshot(&self) -> bool {
+        self.from_snapshot
+    }
+}
+
+/// Sent when a rollback finds that a predicted blueprint, assembled at `frame`, wasn't assembled
+/// during the resimulation. The entity has been despawned.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct BlueprintMispredicted {
+    pub entity: Entity,
+    pub frame: FrameNumber,
+}
+
+/// Sent when a [`TimewarpCommands`] command fails to apply to `entity` at `frame`.
+///
+/// [`TimewarpCommands`]: crate::prelude::TimewarpCommands
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct TimewarpCommandError {
+    pub entity: Entity,
+    pub frame: FrameNumber,
+    pub error: TimewarpError,
 }
 
 /// Every time a rollback completes, before the `Rollback` resources is removed,
@@ -205,3 +492,75 @@ impl DespawnMarker {
         Self(Some(frame))
     }
 }
+
+/// Insert along with a [`DespawnMarker`] for despawns a rollback mustn't undo, eg ones the server
+/// told us about. `despawn_at_frame` adds it for you.
+///
+/// Other despawns are predicted by our game logic, eg a bullet hitting something, so they are
+/// undone if we rollback to before they happened, and only happen again if the resimulation
+/// inserts another marker.
+#[derive(Default, Component, Debug, Clone, Copy, PartialEq)]
+pub struct AuthoritativeDespawn;
+
+/// Entities spawned by a [`KeyedSpawner`](crate::prelude::KeyedSpawner), and the frame they were
+/// spawned. Entries older than the rollback window are pruned.
+#[derive(Resource, Debug, Default)]
+pub struct KeyedSpawns {
+    entities: HashMap<SpawnKey, (Entity, FrameNumber)>,
+    /// spawned during frames we are resimulating, and not spawned again yet
+    pending: HashMap<SpawnKey, Entity>,
+}
+
+impl KeyedSpawns {
+    /// the entity spawned with this key, if we still know about it
+    pub fn entity(&self, key: SpawnKey) -> Option<Entity> {
+        self.entities.get(&key).map(|(entity, _)| *entity)
+    }
+    /// the frame the entity with this key was spawned
+    pub fn spawn_frame(&self, key: SpawnKey) -> Option<FrameNumber> {
+        self.entities.get(&key).map(|(_, frame)| *frame)
+    }
+    /// number of keyed entities spawned within the rollback window
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+    pub(crate) fn record(&mut self, key: SpawnKey, entity: Entity, frame: FrameNumber) {
+        self.pending.remove(&key);
+        self.entities.insert(key, (entity, frame));
+    }
+    /// marks everything spawned at `frame` or later as pending a respawn, returning the entities
+    /// and the frames they were spawned
+    pub(crate) fn begin_resimulating_from(
+        &mut self,
+        frame: FrameNumber,
+    ) -> Vec<(Entity, FrameNumber)> {
+        for (key, (entity, spawn_frame)) in self.entities.iter() {
+            if *spawn_frame >= frame {
+                self.pending.insert(*key, *entity);
+            }
+        }
+        self.pending
+            .iter()
+            .map(|(key, entity)| (*entity, self.spawn_frame(*key).unwrap_or(frame)))
+            .collect()
+    }
+    /// entities that weren't spawned again during the resimulation, which are forgotten
+    pub(crate) fn take_unspawned(&mut self) -> Vec<Entity> {
+        for key in self.pending.keys() {
+            self.entities.remove(key);
+        }
+        self.pending.drain().map(|(_, entity)| entity).collect()
+    }
+    /// forget a key, eg once the server's entity for it has taken over.
+    pub(crate) fn forget(&mut self, key: SpawnKey) {
+        self.entities.remove(&key);
+        self.pending.remove(&key);
+    }
+    pub(crate) fn prune_older_than(&mut self, frame: FrameNumber) {
+        self.entities
+            .retain(|_, (_, spawn_frame)| *spawn_frame >= frame);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::prelude::*;
+use crate::registry::TimewarpRegistry;
+use bevy::{prelude::*, utils::HashSet};
+use std::any::TypeId;
+
+/// Restores the present values of one component type after a rewind.
+pub(crate) type RestoreFn = Box<dyn FnOnce(&mut World)>;
+
+/// Limits which entities and registered component types are rewound by
+/// [`TimewarpWorldTraits::with_rewound_to_filtered`]. The default rewinds everything.
+#[derive(Default, Debug, Clone)]
+pub struct RewindFilter {
+    entities: Option<HashSet<Entity>>,
+    components: Option<HashSet<TypeId>>,
+}
+
+impl RewindFilter {
+    /// rewinds all entities and all registered components
+    pub fn all() -> Self {
+        Self::default()
+    }
+    /// only rewind these entities
+    pub fn with_entities(mut self, entities: impl IntoIterator<Item = Entity>) -> Self {
+        self.entities
+            .get_or_insert_with(HashSet::default)
+            .extend(entities);
+        self
+    }
+    /// only rewind component `T` (can be called multiple times to include more components)
+    pub fn with_component<T: TimewarpComponent>(mut self) -> Self {
+        self.components
+            .get_or_insert_with(HashSet::default)
+            .insert(TypeId::of::<T>());
+        self
+    }
+    pub fn includes_entity(&self, entity: Entity) -> bool {
+        match &self.entities {
+            Some(entities) => entities.contains(&entity),
+            None => true,
+        }
+    }
+    pub fn includes_component(&self, type_id: TypeId) -> bool {
+        match &self.components {
+            Some(components) => components.contains(&type_id),
+            None => true,
+        }
+    }
+}
+
+/// The entities that were alive at the frame [`TimewarpWorldTraits::with_rewound_to`] rewound to,
+/// ie. at least one of their rewound components was alive then. Other entities weren't spawned
+/// yet or had already died, so they still hold their present values and should be skipped.
+#[derive(Default, Debug, Clone)]
+pub struct RewoundEntities(HashSet<Entity>);
+
+impl RewoundEntities {
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.0.contains(&entity)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.0.iter().copied()
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// For server-side lag compensation: temporarily put registered components back to how they
+/// were at a past frame, run some code, then restore the present.
+pub trait TimewarpWorldTraits {
+    /// Runs `f` with all registered components set to their values at `frame`.
+    /// See [`with_rewound_to_filtered`][TimewarpWorldTraits::with_rewound_to_filtered].
+    fn with_rewound_to<R>(
+        &mut self,
+        frame: FrameNumber,
+        f: impl FnOnce(&mut World, &RewoundEntities) -> R,
+    ) -> Result<R, TimewarpError>;
+    /// Runs `f` with the registered components matching `filter` set to their values at `frame`,
+    /// as recorded in their [`ComponentHistory`]. Present values are restored afterwards.
+    ///
+    /// Values are swapped in place, bypassing change detection, and history is not modified.
+    /// Only components which exist both now and at `frame` are rewound – we don't insert or remove
+    /// components here, since that would leave traces in `RemovedComponents` and `Added<T>`.
+    /// So `f` is also given the [`RewoundEntities`] alive at `frame`, and should skip the rest.
+    ///
+    /// Fails with `FrameInFuture` or `FrameTooOld` if `frame` is outside the rollback window.
+    fn with_rewound_to_filtered<R>(
+        &mut self,
+        frame: FrameNumber,
+        filter: &RewindFilter,
+        f: impl FnOnce(&mut World, &RewoundEntities) -> R,
+    ) -> Result<R, TimewarpError>;
+}
+
+impl TimewarpWorldTraits for World {
+    fn with_rewound_to<R>(
+        &mut self,
+        frame: FrameNumber,
+        f: impl FnOnce(&mut World, &RewoundEntities) -> R,
+    ) -> Result<R, TimewarpError> {
+        self.with_rewound_to_filtered(frame, &RewindFilter::all(), f)
+    }
+
+    fn with_rewound_to_filtered<R>(
+        &mut self,
+        frame: FrameNumber,
+        filter: &RewindFilter,
+        f: impl FnOnce(&mut World, &RewoundEntities) -> R,
+    ) -> Result<R, TimewarpError> {
+        let current_frame = self.resource::<GameClock>().frame();
+        let rollback_window = self.resource::<TimewarpConfig>().rollback_window();
+        if frame > current_frame {
+            return Err(TimewarpError::FrameInFuture);
+        }
+        // same limit as despawn_at_frame, older values have left the ComponentHistory
+        if current_frame - frame >= rollback_window {
+            return Err(TimewarpError::FrameTooOld);
+        }
+        let rewinders = self
+            .get_resource::<TimewarpRegistry>()
+            .expect("TimewarpRegistry resource expected")
+            .components
+            .iter()
+            .filter(|reg| filter.includes_component(reg.type_id))
+            .map(|reg| reg.rewind)
+            .collect::<Vec<_>>();
+
+        let mut rewound = RewoundEntities::default();
+        let restorers = rewinders
+            .into_iter()
+            .map(|rewind| rewind(self, frame, filter, &mut rewound))
+            .collect::<Vec<_>>();
+
+        // restores the present when dropped, even if `f` panics
+        let guard = RestoreGuard {
+            world: self,
+            restorers,
+        };
+        Ok(f(guard.world, &rewound))
+    }
+}
+
+struct RestoreGuard<'w> {
+    world: &'w mut World,
+    restorers: Vec<RestoreFn>,
+}
+
+impl Drop for RestoreGuard<'_> {
+    fn drop(&mut self) {
+        for restore in std::mem::take(&mut self.restorers) {
+            restore(self.world);
+        }
+    }
+}
+
+/// swaps in values of T from `frame`, returning a fn to put the present values back.
+/// Entities with a T alive at `frame` are added to `rewound`.
+pub(crate) fn rewind_component<T: TimewarpComponent>(
+    world: &mut World,
+    frame: FrameNumber,
+    filter: &RewindFilter,
+    rewound: &mut RewoundEntities,
+) -> RestoreFn {
+    let mut q =
+        world.query_filtered::<(Entity, &mut T, &ComponentHistory<T>), Without<NoRollback>>();
+    let mut present_values = Vec::new();
+    for (entity, mut comp, ch) in q.iter_mut(world) {
+        if !filter.includes_entity(entity) {
+            continue;
+        }
+        let Some(past_val) = ch.alive_value_at_frame(frame) else {
+            trace!(
+                "Not rewinding {entity:?} {}, not alive at {frame}",
+                ch.type_name()
+            );
+            continue;
+        };
+        let present_val = std::mem::replace(comp.bypass_change_detection(), past_val.clone());
+        present_values.push((entity, present_val));
+        rewound.0.insert(entity);
+    }
+    Box::new(move |world: &mut World| {
+        for (entity, present_val) in present_values {
+            if let Some(mut comp) = world.get_mut::<T>(entity) {
+                *comp.bypass_change_detection() = present_val;
+            } else {
+                warn!(
+                    "{entity:?} lost its {} during rewind, can't restore present value",
+                    std::any::type_name::<T>()
+                );
+            }
+        }
+    })
+}
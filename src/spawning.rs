@@ -0,0 +1,47 @@
+use crate::prelude::*;
+use bevy::{
+    ecs::system::{EntityCommands, SystemParam},
+    prelude::*,
+};
+
+/// Spawns entities identified by a deterministic [`SpawnKey`].
+///
+/// If a rollback resimulates the frame a keyed entity was spawned, spawning the same key again
+/// reuses the original `Entity` instead of making a new one. Keyed entities the resimulation
+/// doesn't spawn again are despawned when the rollback completes.
+///
+/// ```rust,ignore
+/// fn shoot(mut spawner: KeyedSpawner, q: Query<(&Player, &Transform), With<Firing>>) {
+///     for (player, transform) in q.iter() {
+///         let key = SpawnKey(player.id << 32 | player.shots_fired);
+///         spawner.spawn(key, BulletBundle::new(*transform));
+///     }
+/// }
+/// ```
+#[derive(SystemParam)]
+pub struct KeyedSpawner<'w, 's> {
+    commands: Commands<'w, 's>,
+    spawns: ResMut<'w, KeyedSpawns>,
+    game_clock: Res<'w, GameClock>,
+}
+
+impl<'w, 's> KeyedSpawner<'w, 's> {
+    /// spawn a new entity with `bundle`, or insert it into the entity previously spawned with
+    /// this key, if there is one.
+    pub fn spawn<B: Bundle>(&mut self, key: SpawnKey, bundle: B) -> EntityCommands<'_> {
+        let frame = self.game_clock.frame();
+        let existing = self
+            .spawns
+            .entity(key)
+            .filter(|entity| self.commands.get_entity(*entity).is_some());
+        let entity = match existing {
+            Some(entity) => {
+                trace!("Reusing {entity:?} for {key:?} @ {frame}");
+                self.commands.entity(entity).insert((bundle, key)).id()
+            }
+            None => self.commands.spawn((bundle, key)).id(),
+        };
+        self.spawns.record(key, entity, frame);
+        self.commands.entity(entity)
+    }
+}
@@ -13,6 +13,7 @@ pub(crate) mod prefix_start_rollback;
 
 /// footgun protection - in case your clock ticking fn isn't running properly, this avoids
 /// timewarp rolling back if the clock won't advance, since that would be an infinite loop.
+#[allow(clippy::unnecessary_unwrap)]
 pub(crate) fn sanity_check(
     game_clock: Res<GameClock>,
     opt_rb: Option<Res<Rollback>>,
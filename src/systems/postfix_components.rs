@@ -1,5 +1,6 @@
 use crate::prelude::*;
-use bevy::prelude::*;
+use crate::registry::{add_dynamic_history, TimewarpRegistry};
+use bevy::{ecs::system::StaticSystemParam, prelude::*};
 /*
     Postfix Sets
 
@@ -34,6 +35,217 @@ pub(crate) fn remove_components_from_despawning_entities<T: TimewarpComponent>(
     }
 }
 
+/// Children would be despawned by `despawn_recursive` along with their parent, so they get a
+/// copy of the parent's [`DespawnMarker`] (and [`AuthoritativeDespawn`]) and are cleaned up and
+/// revived along with it.
+pub(crate) fn propagate_despawn_markers_to_children(
+    q: Query<(Entity, &DespawnMarker, Has<AuthoritativeDespawn>), Added<DespawnMarker>>,
+    q_children: Query<&Children>,
+    q_markers: Query<(), With<DespawnMarker>>,
+    mut commands: Commands,
+) {
+    for (entity, marker, authoritative) in q.iter() {
+        for child in q_children.iter_descendants(entity) {
+            if q_markers.contains(child) {
+                continue;
+            }
+            trace!("propagating {marker:?} from {entity:?} to child {child:?}");
+            if authoritative {
+                commands
+                    .entity(child)
+                    .insert((*marker, AuthoritativeDespawn));
+            } else {
+                commands.entity(child).insert(*marker);
+            }
+        }
+    }
+}
+
+/// add a ParentHistory to timewarp entities that get a parent.
+pub(crate) fn add_parent_history(
+    q: Query<(Entity, &Parent), (With<TimewarpStatus>, Without<ParentHistory>)>,
+    mut commands: Commands,
+    game_clock: Res<GameClock>,
+    timewarp_config: Res<TimewarpConfig>,
+) {
+    for (entity, parent) in q.iter() {
+        commands.entity(entity).insert(ParentHistory::with_capacity(
+            timewarp_config.rollback_window as usize,
+            game_clock.frame(),
+            Some(parent.get()),
+        ));
+    }
+}
+
+/// record the current parent, or lack of one, in the ParentHistory
+pub(crate) fn record_parent_history(
+    mut q: Query<(Entity, Option<&Parent>, &mut ParentHistory)>,
+    game_clock: Res<GameClock>,
+) {
+    for (entity, parent, mut ph) in q.iter_mut() {
+        if let Err(err) = ph
+            .values
+            .insert(game_clock.frame(), parent.map(|p| p.get()))
+        {
+            error!(
+                "{entity:?} Couldn't record parent @ {:?}: {err:?}",
+                game_clock.frame()
+            );
+        }
+    }
+}
+
+/// Like `record_component_history`, `add_timewarp_components`, `apply_snapshots_during_rollback`
+/// and `remove_components_from_despawning_entities`, for every component registered at runtime.
+pub(crate) fn record_dynamic_component_history(world: &mut World) {
+    let registered = world
+        .resource::<TimewarpRegistry>()
+        .dynamic_components
+        .clone();
+    let frame = world.resource::<GameClock>().frame();
+    let apply_snapshots =
+        world.contains_resource::<Rollback>() && world.resource::<SnapshotBatches>().in_use();
+    let type_registry = world.resource::<AppTypeRegistry>().clone();
+    let type_registry = type_registry.read();
+    for reg in registered.iter() {
+        // entities with the component, and entities with a history of it that lost it
+        let component_id = world.components().get_id(reg.type_id);
+        let mut entities = match component_id {
+            Some(component_id) => QueryBuilder::<Entity, Without<NoRollback>>::new(world)
+                .with_id(component_id)
+                .build()
+                .iter(world)
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        let mut lost =
+            QueryBuilder::<(Entity, &DynamicComponentHistory), Without<NoRollback>>::new(world);
+        if let Some(component_id) = component_id {
+            lost.without_id(component_id);
+        }
+        entities.extend(
+            lost.build()
+                .iter(world)
+                .filter(|(_, dch)| dch.values.contains_key(&reg.type_id))
+                .map(|(entity, _)| entity),
+        );
+        for entity in entities {
+            let mut entity_mut = world.entity_mut(entity);
+            // like `remove_components_from_despawning_entities`, and `rekill_components_during_rollback`
+            // when a rollback resimulates the frames before an authoritative despawn
+            let despawned = entity_mut.get::<DespawnMarker>().is_some_and(
+                |marker| !matches!(marker.0, Some(despawn_frame) if despawn_frame > frame),
+            );
+            if despawned {
+                reg.reflect.remove(&mut entity_mut);
+            } else if apply_snapshots {
+                // a snapshot for the frame we just resimulated is authoritative
+                let snapshot = entity_mut
+                    .get::<DynamicServerSnapshot>()
+                    .and_then(|dss| dss.at_frame(reg.type_id, frame))
+                    .cloned();
+                if let Some(snapshot) = snapshot {
+                    reg.reflect.apply_or_insert(
+                        &mut entity_mut,
+                        snapshot.0.as_ref(),
+                        &type_registry,
+                    );
+                }
+            }
+            let value = reg
+                .reflect
+                .reflect(EntityRef::from(&entity_mut))
+                .map(|value| ReflectValue(value.clone_value()));
+            add_dynamic_history(&mut entity_mut, frame);
+            let mut dch = entity_mut
+                .get_mut::<DynamicComponentHistory>()
+                .expect("Just inserted");
+            if let Err(err) = dch.insert(reg.type_id, reg.type_path, frame, value) {
+                error!(
+                    "{entity:?} Couldn't record {} @ {frame}: {err:?}",
+                    reg.type_path
+                );
+            }
+        }
+    }
+}
+
+/// Set T to its snapshot value from `delay` frames ago, for interpolated entities.
+pub(crate) fn interpolate_components<T: TimewarpComponent + TimewarpInterpolate>(
+    mut q: Query<(&mut T, &ServerSnapshot<T>, &PredictionMode), Without<NoRollback>>,
+    game_clock: Res<GameClock>,
+) {
+    for (mut comp, ss, mode) in q.iter_mut() {
+        let PredictionMode::Interpolated { delay } = *mode else {
+            continue;
+        };
+        if let Some(val) = ss.interpolated_at(game_clock.frame().saturating_sub(delay)) {
+            *comp = val;
+        }
+    }
+}
+
+/// During rollback, a snapshot for the frame we just resimulated is authoritative, so use it
+/// instead of our resimulated value. This matters when updates for different entities arrived
+/// for different frames within the rollback range, so only runs once [`SnapshotBatches`] are
+/// in use.
+pub(crate) fn apply_snapshots_during_rollback<T: TimewarpComponent>(
+    mut q: Query<
+        (
+            &mut T,
+            &ServerSnapshot<T>,
+            Option<&PredictionMode>,
+            Option<&LocalAuthority<T>>,
+        ),
+        Without<NoRollback>,
+    >,
+    game_clock: Res<GameClock>,
+) {
+    let frame = game_clock.frame();
+    for (mut comp, ss, opt_mode, opt_authority) in q.iter_mut() {
+        if opt_mode.is_some_and(|mode| !mode.is_predicted())
+            || opt_authority.is_some_and(|auth| auth.owns_at(frame))
+        {
+            continue;
+        }
+        if let Some(snap_val) = ss.at_frame(frame) {
+            if *comp != *snap_val {
+                trace!("Applying snapshot for {frame} during rollback: {snap_val:?}");
+                *comp = snap_val.clone();
+            }
+        }
+    }
+}
+
+/// Every `record_component_history` system runs in this set, so systems which must change T before
+/// it's recorded don't need to know if T was registered on its own or in a bundle.
+#[derive(SystemSet, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct RecordComponentHistory;
+
+/// The params of [`record_component_history`], so a bundle can record each of its components in one system
+pub(crate) type RecordComponentParams<T> = (
+    Query<
+        'static,
+        'static,
+        (
+            Entity,
+            &'static T,
+            &'static mut ComponentHistory<T>,
+            Option<&'static mut TimewarpCorrection<T>>,
+        ),
+        Without<NoRollback>,
+    >,
+    Res<'static, GameClock>,
+    Commands<'static, 'static>,
+    Option<Res<'static, Rollback>>,
+);
+
+/// Like `record_component_history::<T>` for every component of B, which are registered with
+/// `register_rollback_bundle`.
+pub(crate) fn record_bundle_history<B: TimewarpBundle>(params: StaticSystemParam<B::RecordParams>) {
+    B::record_components(params.into_inner());
+}
+
 /// Write current value of component to the ComponentHistory buffer for this frame
 pub(crate) fn record_component_history<T: TimewarpComponent>(
     mut q: Query<
@@ -96,15 +308,57 @@ pub(crate) fn record_component_history<T: TimewarpComponent>(
     }
 }
 
+/// Servers resimulating because of late inputs need to tell clients about values that changed.
+/// Compares the resimulated value to what we recorded the first time we simulated this frame.
+pub(crate) fn record_server_corrections<T: TimewarpComponent>(
+    mut q: Query<
+        (
+            Entity,
+            &T,
+            &ComponentHistory<T>,
+            Option<&mut ServerCorrection<T>>,
+        ),
+        Without<NoRollback>,
+    >,
+    game_clock: Res<GameClock>,
+    rb: Res<Rollback>,
+    mut commands: Commands,
+) {
+    let frame = game_clock.frame();
+    for (entity, comp, comp_hist, opt_correction) in q.iter_mut() {
+        if comp_hist.at_frame(frame) == Some(comp) {
+            continue;
+        }
+        trace!(
+            "{entity:?} {} changed during server resimulation @ {frame}",
+            comp_hist.type_name()
+        );
+        if let Some(mut correction) = opt_correction {
+            if correction.rollback_range != rb.range {
+                *correction = ServerCorrection::new(rb.range.clone());
+            }
+            correction.frames.push(frame);
+        } else {
+            let mut correction = ServerCorrection::<T>::new(rb.range.clone());
+            correction.frames.push(frame);
+            commands.entity(entity).insert(correction);
+        }
+    }
+}
+
 /// add the ComponentHistory<T> and ServerSnapshot<T> whenever an entity gets the T component.
+/// (servers don't get a ServerSnapshot<T>)
 /// NB: you must have called `app.register_rollback::<T>()` for this to work.
 pub(crate) fn add_timewarp_components<T: TimewarpComponent, const CORRECTION_LOGGING: bool>(
-    q: Query<(Entity, &T), (Added<T>, Without<NoRollback>, Without<ComponentHistory<T>>)>,
+    q: Query<
+        (Entity, &T, Has<TimewarpStatus>, Has<ServerSnapshot<T>>),
+        (Added<T>, Without<NoRollback>, Without<ComponentHistory<T>>),
+    >,
     mut commands: Commands,
     game_clock: Res<GameClock>,
     timewarp_config: Res<TimewarpConfig>,
 ) {
-    for (e, comp) in q.iter() {
+    for (e, comp, has_status, has_ss) in q.iter() {
         // insert component value at this frame, since the system that records it won't run
         // if a rollback is happening this frame. and if it does it just overwrites
         let mut comp_history = ComponentHistory::<T>::with_capacity(
@@ -122,13 +376,21 @@ pub(crate) fn add_timewarp_components<T: TimewarpComponent, const CORRECTION_LOG
             game_clock.frame(),
             comp.clone(),
         );
-        commands.entity(e).insert((
-            TimewarpStatus::new(0),
-            comp_history,
-            // server snapshots are sent event n frames, so there are going to be lots of Nones in
-            // the sequence buffer. increase capacity accordingly.
-            // TODO compute based on snapshot send rate.
-            ServerSnapshot::<T>::with_capacity(timewarp_config.rollback_window as usize * 60), // TODO yuk
-        ));
+        let mut ec = commands.entity(e);
+        ec.insert(comp_history);
+        // entities respawned by a resimulation may still have these
+        if !has_status {
+            ec.insert(TimewarpStatus::new(0));
+        }
+        // servers and peers never receive snapshots.
+        if !timewarp_config.receives_snapshots() || has_ss {
+            continue;
+        }
+        // server snapshots are sent event n frames, so there are going to be lots of Nones in
+        // the sequence buffer. increase capacity accordingly.
+        // TODO compute based on snapshot send rate.
+        ec.insert(ServerSnapshot::<T>::with_capacity(
+            timewarp_config.rollback_window as usize * 60,
+        )); // TODO yuk
     }
 }
@@ -8,26 +8,57 @@ use bevy::prelude::*;
 
 /// Once a [`DespawnMarker`] has been around for `rollback_frames`, do the actual despawn.
 /// also for new DespawnMarkers that don't have a frame yet, add one.
+///
+/// Anything at least `rollback_frames` old is despawned, so markers aren't leaked if the clock
+/// skips over the exact frame, eg due to a resync.
+///
+/// In peer-to-peer mode, we can despawn as soon as the despawn frame is confirmed, since a
+/// rollback can't revive the entity after that.
 pub(crate) fn despawn_entities_with_elapsed_despawn_marker(
-    mut q: Query<(Entity, &mut DespawnMarker)>,
+    mut q: Query<(Entity, &mut DespawnMarker, Option<&Parent>)>,
     mut commands: Commands,
     game_clock: Res<GameClock>,
     timewarp_config: Res<TimewarpConfig>,
+    confirmed_frame: Res<ConfirmedFrame>,
+    mut rb_stats: ResMut<RollbackStats>,
 ) {
-    for (entity, mut marker) in q.iter_mut() {
+    // only set in peer-to-peer mode
+    let confirmed_frame = confirmed_frame.frame();
+    let mut elapsed = Vec::new();
+    for (entity, mut marker, parent) in q.iter_mut() {
         if marker.0.is_none() {
             marker.0 = Some(game_clock.frame());
             continue;
         }
-        if (marker.0.expect("Despawn marker should have a frame!")
-            + timewarp_config.rollback_window)
-            == game_clock.frame()
-        {
-            trace!(
-                "💀 Doing actual despawn of {entity:?} at frame {:?}",
-                game_clock.frame()
-            );
-            commands.entity(entity).despawn_recursive();
+        let despawn_frame = marker.0.expect("Despawn marker should have a frame!");
+        let confirmed = confirmed_frame.is_some_and(|cf| despawn_frame <= cf);
+        if confirmed || despawn_frame + timewarp_config.rollback_window <= game_clock.frame() {
+            elapsed.push((entity, parent.map(|p| p.get())));
         }
     }
+    rb_stats.pending_despawns = q.iter().len() - elapsed.len();
+    for (entity, parent) in elapsed.iter() {
+        // children are despawned recursively along with their parent
+        if parent.is_some_and(|p| elapsed.iter().any(|(e, _)| *e == p)) {
+            continue;
+        }
+        trace!(
+            "💀 Doing actual despawn of {entity:?} at frame {:?}",
+            game_clock.frame()
+        );
+        commands.entity(*entity).despawn_recursive();
+    }
+}
+
+/// forget keyed spawns from before the rollback window, since we can't resimulate them.
+pub(crate) fn prune_keyed_spawns(
+    mut keyed_spawns: ResMut<KeyedSpawns>,
+    game_clock: Res<GameClock>,
+    timewarp_config: Res<TimewarpConfig>,
+) {
+    keyed_spawns.prune_older_than(
+        game_clock
+            .frame()
+            .saturating_sub(timewarp_config.rollback_window),
+    );
 }
@@ -1,5 +1,9 @@
 use crate::prelude::*;
-use bevy::prelude::*;
+use bevy::{
+    ecs::{component::ComponentId, system::CommandQueue},
+    prelude::*,
+    utils::HashSet,
+};
 /*
     NOTE: Timewarp Prefix Systems run at the top of FixedUpdate:
         * RIGHT BEFORE THE GameClock IS INCREMENTED.
@@ -15,20 +19,234 @@ pub(crate) fn unwrap_blueprints_at_target_frame<T: Component + std::fmt::Debug +
     mut commands: Commands,
     game_clock: Res<GameClock>,
     rb: Option<Res<Rollback>>,
+    timewarp_config: Res<TimewarpConfig>,
 ) {
+    let is_server = timewarp_config.is_server();
     for (e, abaf) in q.iter() {
-        // yes, blueprints assembled 1 frame late on clients. see NOTES
-        if abaf.frame != **game_clock {
+        let Some(exact) = abaf.unwrap_now(**game_clock, is_server) else {
             // debug!("Not assembling, gc={game_clock:?} {abaf:?}");
             continue;
-        }
+        };
         debug!(
-            "🎁 {game_clock:?} Unwrapping {abaf:?} @ {game_clock:?} rb:{rb:?} {}",
+            "🎁 {game_clock:?} Unwrapping {abaf:?} @ {game_clock:?} exact:{exact} rb:{rb:?} {}",
             std::any::type_name::<T>()
         );
-        commands
-            .entity(e)
-            .insert(abaf.component.clone())
+        let mut ec = commands.entity(e);
+        // with the spawn state, we assemble during the exact frame, which is simulated next.
+        if let Some(spawn_state) = abaf.spawn_state.as_ref().filter(|_| exact) {
+            spawn_state.apply(&mut ec);
+        }
+        if abaf.predicted {
+            ec.remove::<PendingBlueprintAssembly>()
+                .insert(PredictedBlueprint {
+                    frame: **game_clock + 1,
+                });
+        }
+        ec.insert(abaf.component.clone())
             .remove::<AssembleBlueprintAtFrame<T>>();
     }
 }
+
+/// The blueprint assembly fn registered with `register_blueprint_with_assembler::<T, B>`
+#[derive(Resource)]
+pub(crate) struct BlueprintAssembler<T: Component, B: Bundle>(
+    pub(crate) fn(&mut EntityWorldMut, &T) -> B,
+);
+
+/// Like [`unwrap_blueprints_at_target_frame`], but also runs the registered assembler, inserts
+/// the bundle it returns, and records what was added in an [`AssembledBlueprint<T>`].
+pub(crate) fn unwrap_and_assemble_blueprints<T: Component + std::fmt::Debug + Clone, B: Bundle>(
+    world: &mut World,
+) {
+    let current_frame = world.resource::<GameClock>().frame();
+    let is_server = world.resource::<TimewarpConfig>().is_server();
+    let ready = world
+        .query::<(Entity, &AssembleBlueprintAtFrame<T>)>()
+        .iter(world)
+        .filter_map(|(e, abaf)| {
+            abaf.unwrap_now(current_frame, is_server)
+                .map(|exact| (e, exact))
+        })
+        .collect::<Vec<_>>();
+    if ready.is_empty() {
+        return;
+    }
+    let assembler = world.resource::<BlueprintAssembler<T, B>>().0;
+    for (entity, exact) in ready {
+        let abaf = world
+            .entity_mut(entity)
+            .take::<AssembleBlueprintAtFrame<T>>()
+            .expect("Just queried for the blueprint");
+        debug!(
+            "🎁 {current_frame} Unwrapping and assembling {abaf:?} exact:{exact} {}",
+            std::any::type_name::<T>()
+        );
+        if let Some(spawn_state) = abaf.spawn_state.as_ref().filter(|_| exact) {
+            let mut queue = CommandQueue::default();
+            let mut commands = Commands::new(&mut queue, world);
+            spawn_state.apply(&mut commands.entity(entity));
+            queue.apply(world);
+        }
+        let mut entity_mut = world.entity_mut(entity);
+        entity_mut.insert(abaf.component.clone());
+        if abaf.predicted {
+            entity_mut
+                .remove::<PendingBlueprintAssembly>()
+                .insert(PredictedBlueprint {
+                    frame: current_frame + 1,
+                });
+        }
+        let before = component_ids(&entity_mut);
+        let bundle = assembler(&mut entity_mut, &abaf.component);
+        // the bundle overwrites any of its components the entity already has, so keep their
+        // values to put back when unassembling. Until B has been inserted once we don't know
+        // which components it has, so keep everything.
+        let overlap = match bundle_component_ids::<B>(world) {
+            Some(ids) => before.intersection(&ids).copied().collect(),
+            None => before.clone(),
+        };
+        let mut replaced = reflect_components(world, entity, &overlap);
+        let mut entity_mut = world.entity_mut(entity);
+        entity_mut.insert(bundle);
+        let added = component_ids(&entity_mut)
+            .difference(&before)
+            .copied()
+            .collect::<Vec<_>>();
+        let bundle_ids = bundle_component_ids::<B>(world).expect("Just inserted the bundle");
+        replaced.retain(|(id, _)| bundle_ids.contains(id));
+        for id in before.intersection(&bundle_ids) {
+            if !replaced.iter().any(|(replaced_id, _)| replaced_id == id) {
+                warn!(
+                    "Assembling {} on {entity:?} replaced {:?}, which isn't reflectable, so \
+                     unassembling will remove it",
+                    std::any::type_name::<T>(),
+                    world.components().get_name(*id)
+                );
+            }
+        }
+        world
+            .entity_mut(entity)
+            .insert(AssembledBlueprint::<T>::new(
+                abaf.frame,
+                current_frame + 1,
+                abaf.spawn_state,
+                added,
+                replaced,
+                remove_bundle::<B>,
+            ));
+    }
+}
+
+fn component_ids(entity: &EntityWorldMut) -> HashSet<ComponentId> {
+    entity.archetype().components().collect()
+}
+
+/// only known once the bundle has been inserted somewhere
+fn bundle_component_ids<B: Bundle>(world: &World) -> Option<HashSet<ComponentId>> {
+    let bundles = world.bundles();
+    let id = bundles.get_id(std::any::TypeId::of::<B>())?;
+    Some(bundles.get(id)?.components().iter().copied().collect())
+}
+
+/// Copies of the components of `entity` with these ids that are `#[reflect(Component)]`
+fn reflect_components(
+    world: &World,
+    entity: Entity,
+    ids: &HashSet<ComponentId>,
+) -> Vec<(ComponentId, Box<dyn Reflect>)> {
+    let type_registry = world.resource::<AppTypeRegistry>().read();
+    let entity_ref = world.entity(entity);
+    ids.iter()
+        .filter_map(|id| {
+            let type_id = world.components().get_info(*id)?.type_id()?;
+            let reflect = type_registry.get_type_data::<ReflectComponent>(type_id)?;
+            Some((*id, reflect.reflect(entity_ref)?.clone_value()))
+        })
+        .collect()
+}
+
+fn remove_bundle<B: Bundle>(entity: &mut EntityWorldMut) {
+    entity.remove::<B>();
+}
+
+/// Called at the start of a rollback via the registry, see `unassemble_blueprints`.
+/// Blueprints assembled during the frames we are about to resimulate have their assembled
+/// bundle removed, and are wrapped up again, to be reassembled at the right frame.
+pub(crate) fn unassemble_blueprints_of_type<T: Component + std::fmt::Debug + Clone>(
+    world: &mut World,
+    rb_start: FrameNumber,
+) {
+    let unassemble = world
+        .query::<(Entity, &AssembledBlueprint<T>)>()
+        .iter(world)
+        .filter(|(_, assembled)| assembled.assembled_frame >= rb_start)
+        .map(|(e, _)| e)
+        .collect::<Vec<_>>();
+    for entity in unassemble {
+        let mut entity_mut = world.entity_mut(entity);
+        let assembled = entity_mut
+            .take::<AssembledBlueprint<T>>()
+            .expect("Just queried for the assembled blueprint");
+        debug!(
+            "Unassembling {entity:?} {} assembled @ {}, rollback starts at {rb_start}",
+            std::any::type_name::<T>(),
+            assembled.assembled_frame
+        );
+        (assembled.remove)(&mut entity_mut);
+        if !assembled.replaced.is_empty() {
+            restore_replaced_components(world, entity, assembled.replaced);
+        }
+        let mut entity_mut = world.entity_mut(entity);
+        let Some(component) = entity_mut.take::<T>() else {
+            continue;
+        };
+        entity_mut.insert(AssembleBlueprintAtFrame {
+            component,
+            frame: assembled.blueprint_frame,
+            spawn_state: assembled.spawn_state,
+            predicted: false,
+        });
+    }
+}
+
+/// Called at the start of a rollback via the registry, see `unassemble_predicted_blueprints`.
+/// Removes the blueprint from a predicted entity, undoing its assembly if it had an assembler.
+/// It isn't wrapped up again, the resimulation has to predict it again.
+pub(crate) fn unassemble_predicted_blueprint<T: Component + std::fmt::Debug + Clone>(
+    world: &mut World,
+    entity: Entity,
+) {
+    let mut entity_mut = world.entity_mut(entity);
+    if let Some(assembled) = entity_mut.take::<AssembledBlueprint<T>>() {
+        (assembled.remove)(&mut entity_mut);
+        if !assembled.replaced.is_empty() {
+            restore_replaced_components(world, entity, assembled.replaced);
+        }
+    }
+    world.entity_mut(entity).remove::<T>();
+}
+
+/// Puts back the values the assembled bundle overwrote, see [`AssembledBlueprint`].
+fn restore_replaced_components(
+    world: &mut World,
+    entity: Entity,
+    replaced: Vec<(ComponentId, Box<dyn Reflect>)>,
+) {
+    let type_registry = world.resource::<AppTypeRegistry>().clone();
+    let type_registry = type_registry.read();
+    for (id, value) in replaced {
+        let Some(reflect) = world
+            .components()
+            .get_info(id)
+            .and_then(|info| info.type_id())
+            .and_then(|type_id| type_registry.get_type_data::<ReflectComponent>(type_id))
+        else {
+            continue;
+        };
+        reflect.insert(
+            &mut world.entity_mut(entity),
+            value.as_ref(),
+            &type_registry,
+        );
+    }
+}
@@ -6,7 +6,8 @@
 
 */
 use crate::prelude::*;
-use bevy::prelude::*;
+use crate::registry::TimewarpRegistry;
+use bevy::{prelude::*, utils::HashMap};
 
 /// for when we add the ComponentHistory via a trait on EntityMut which doesn't know the error reporting setting
 pub(crate) fn enable_error_correction_for_new_component_histories<T: TimewarpComponent>(
@@ -34,3 +35,80 @@ pub(crate) fn record_component_death<T: TimewarpComponent>(
         }
     }
 }
+
+/// Lockstep: decide if we simulate this tick, ie if the inputs for the next frame are all in.
+/// With no input buffers at all, there's nobody to wait for.
+pub(crate) fn update_lockstep_stats(
+    game_clock: Res<GameClock>,
+    confirmed_frame: Res<ConfirmedFrame>,
+    mut stats: ResMut<LockstepStats>,
+) {
+    let ready = match confirmed_frame.frame() {
+        Some(frame) => frame > **game_clock,
+        None => true,
+    };
+    if !ready {
+        trace!("Lockstep stalled waiting for inputs. {game_clock:?} {confirmed_frame:?}");
+    }
+    stats.update(ready);
+}
+
+/// Clients: server entities with a [`ServerSpawnKey`] take over the history of the
+/// [`PredictedSpawn`] with the same key, which is then despawned.
+/// Predictions that have waited longer than the timeout are despawned.
+pub(crate) fn match_predicted_spawns(world: &mut World) {
+    let current_frame = world.resource::<GameClock>().frame();
+    let timeout = world.resource::<TimewarpConfig>().predicted_spawn_timeout();
+    let server_entities = world
+        .query::<(Entity, &ServerSpawnKey)>()
+        .iter(world)
+        .map(|(entity, key)| (key.0, entity))
+        .collect::<HashMap<_, _>>();
+    let mut matched = Vec::new();
+    let mut expired = Vec::new();
+    for (entity, mut predicted) in world
+        .query::<(Entity, &mut PredictedSpawn)>()
+        .iter_mut(world)
+    {
+        let frame = *predicted.frame.get_or_insert(current_frame);
+        if let Some(server_entity) = server_entities.get(&predicted.key) {
+            matched.push((predicted.key, entity, *server_entity));
+        } else if current_frame.saturating_sub(frame) >= timeout {
+            expired.push((predicted.key, entity));
+        }
+    }
+    if matched.is_empty() && expired.is_empty() {
+        return;
+    }
+    let registry = world.resource::<TimewarpRegistry>();
+    let adopters = registry
+        .components
+        .iter()
+        .map(|reg| reg.adopt)
+        .collect::<Vec<_>>();
+    let dynamic = registry.dynamic_components.clone();
+    for (key, predicted, server_entity) in matched {
+        debug!("Matched predicted {predicted:?} with server {server_entity:?} for {key:?}");
+        for adopt in adopters.iter() {
+            adopt(world, predicted, server_entity);
+        }
+        for reg in dynamic.iter() {
+            reg.adopt(world, predicted, server_entity);
+        }
+        if let Some(mut server_mut) = world.get_entity_mut(server_entity) {
+            if !server_mut.contains::<TimewarpStatus>() {
+                server_mut.insert(TimewarpStatus::new(0));
+            }
+            // matched, so a later prediction with the same key isn't adopted too
+            server_mut.remove::<ServerSpawnKey>();
+        }
+        // the server owns its lifetime now, so a resimulation shouldn't reuse it
+        world.resource_mut::<KeyedSpawns>().forget(key);
+        despawn_with_children_recursive(world, predicted);
+    }
+    for (key, predicted) in expired {
+        info!("Predicted spawn {predicted:?} for {key:?} timed out, despawning");
+        world.resource_mut::<KeyedSpawns>().forget(key);
+        despawn_with_children_recursive(world, predicted);
+    }
+}
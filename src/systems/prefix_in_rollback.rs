@@ -30,6 +30,49 @@ pub(crate) fn check_for_rollback_completion(
     commands.remove_resource::<Rollback>();
 }
 
+/// When the rollback completes, despawn predicted blueprints the resimulation didn't assemble
+/// again, and tell the game about it.
+pub(crate) fn despawn_mispredicted_blueprints(
+    q: Query<(Entity, &PendingBlueprintAssembly)>,
+    game_clock: Res<GameClock>,
+    rb: Res<Rollback>,
+    mut commands: Commands,
+    mut mispredicted: EventWriter<BlueprintMispredicted>,
+) {
+    if rb.range.end != **game_clock {
+        return;
+    }
+    for (entity, pending) in q.iter() {
+        debug!(
+            "Despawning mispredicted blueprint {entity:?} @ {}",
+            pending.frame
+        );
+        commands.entity(entity).despawn_recursive();
+        mispredicted.send(BlueprintMispredicted {
+            entity,
+            frame: pending.frame,
+        });
+    }
+}
+
+/// When the rollback completes, despawn keyed entities the resimulation didn't spawn again.
+pub(crate) fn despawn_keyed_spawns_not_respawned(
+    game_clock: Res<GameClock>,
+    rb: Res<Rollback>,
+    mut keyed_spawns: ResMut<KeyedSpawns>,
+    mut commands: Commands,
+) {
+    if rb.range.end != **game_clock {
+        return;
+    }
+    for entity in keyed_spawns.take_unspawned() {
+        debug!("Despawning keyed {entity:?}, which wasn't spawned again during {rb:?}");
+        if let Some(ec) = commands.get_entity(entity) {
+            ec.despawn_recursive();
+        }
+    }
+}
+
 /// during rollback, need to re-insert components that were removed, based on stored lifetimes.
 pub(crate) fn rebirth_components_during_rollback<T: TimewarpComponent>(
     q: Query<(Entity, &ComponentHistory<T>), Without<T>>,
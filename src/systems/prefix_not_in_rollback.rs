@@ -8,16 +8,21 @@ use std::cmp::Ordering;
 
 */
 use crate::prelude::*;
+use crate::registry::TimewarpRegistry;
 use bevy::prelude::*;
 
 /// If a new snapshot was added to SS, we may need to initiate a rollback
-pub(crate) fn apply_snapshots_and_maybe_rollback<T: TimewarpComponent>(
+///
+/// For SNAP_ONLY components, we never rollback, mismatching values are applied directly.
+pub(crate) fn apply_snapshots_and_maybe_rollback<T: TimewarpComponent, const SNAP_ONLY: bool>(
     mut q: Query<
         (
             Entity,
             &ServerSnapshot<T>,
             &mut ComponentHistory<T>,
             &mut TimewarpStatus,
+            Option<&PredictionMode>,
+            Option<&LocalAuthority<T>>,
         ),
         Changed<ServerSnapshot<T>>, // this includes Added<>
     >,
@@ -27,20 +32,78 @@ pub(crate) fn apply_snapshots_and_maybe_rollback<T: TimewarpComponent>(
     mut commands: Commands,
     mut rb_stats: ResMut<RollbackStats>,
 ) {
-    for (entity, server_snapshot, mut comp_hist, mut tw_status) in q.iter_mut() {
+    for (entity, server_snapshot, mut comp_hist, mut tw_status, opt_mode, opt_authority) in
+        q.iter_mut()
+    {
         let snap_frame = server_snapshot.values.newest_frame();
 
+        // the newest thing the server told us is that T was removed
+        if let Some(removal_frame) = server_snapshot
+            .newest_removal()
+            .filter(|removal_frame| *removal_frame >= snap_frame)
+        {
+            tw_status.set_snapped_at(removal_frame);
+            if removal_frame > **game_clock
+                || !comp_hist.alive_at_frame(removal_frame)
+                || opt_authority.is_some_and(|auth| auth.owns_at(removal_frame))
+            {
+                // nothing to correct (yet)
+                continue;
+            }
+            comp_hist.report_death_at_frame(removal_frame);
+            if removal_frame == **game_clock
+                || SNAP_ONLY
+                || opt_mode.is_some_and(|mode| !mode.is_predicted())
+            {
+                trace!(
+                    "Removing {entity:?} {} without rollback",
+                    comp_hist.type_name()
+                );
+                commands.entity(entity).remove::<T>();
+                rb_stats.non_rollback_updates += 1;
+            } else {
+                debug!(
+                    "Triggering rollback due to snapshot removal. {entity:?} removal_frame: {removal_frame} {}",
+                    comp_hist.type_name()
+                );
+                rb_ev.send(RollbackRequest::from_snapshot(removal_frame));
+                tw_status.increment_rollback_triggers();
+            }
+            continue;
+        }
+
         if snap_frame == 0 {
             continue;
         }
 
         tw_status.set_snapped_at(snap_frame);
 
+        // we own this component at snap_frame, so the server is just echoing our values back.
+        if opt_authority.is_some_and(|auth| auth.owns_at(snap_frame)) {
+            trace!(
+                "Ignoring snapshot for locally owned {entity:?} {} @ {snap_frame}",
+                comp_hist.type_name()
+            );
+            continue;
+        }
+
         // the value in the SS that we are concerned with, which may possibly trigger a rollback:
         let comp_from_snapshot = server_snapshot
             .at_frame(snap_frame)
             .expect("snap_frame must have a value here");
 
+        match opt_mode.copied().unwrap_or_default() {
+            PredictionMode::Predicted => (),
+            // the interpolation system reads from the SS, nothing more to do
+            PredictionMode::Interpolated { .. } => continue,
+            PredictionMode::Snapped => {
+                trace!("Snapping {entity:?} to {comp_from_snapshot:?} @ {snap_frame}");
+                commands.entity(entity).insert(comp_from_snapshot.clone());
+                rb_stats.non_rollback_updates += 1;
+                continue;
+            }
+        }
+
         // we're in preudpate, the game clock is about to be incremented.
         // so if the snap frame = current clock, we need it inserted right now without rolling back
         // in this case, we don't need to write to comp_hist either, it will happen normally at the end of the frame.
@@ -79,7 +142,11 @@ pub(crate) fn apply_snapshots_and_maybe_rollback<T: TimewarpComponent>(
             }
         }
 
-        if snap_frame < **game_clock {
+        if snap_frame < **game_clock && SNAP_ONLY {
+            trace!("Snapping {entity:?} {comp_from_snapshot:?} from {snap_frame} without rollback");
+            commands.entity(entity).insert(comp_from_snapshot.clone());
+            rb_stats.non_rollback_updates += 1;
+        } else if snap_frame < **game_clock {
             debug!(
                 "Triggering rollback due to snapshot. {entity:?} snap_frame: {snap_frame} {}",
                 comp_hist.type_name()
@@ -87,14 +154,105 @@ pub(crate) fn apply_snapshots_and_maybe_rollback<T: TimewarpComponent>(
 
             // data for frame 100 is the post-physics value at the server, so we need it to be
             // inserted in time for the client to simulate frame 101.
-            rb_ev.send(RollbackRequest::resimulate_this_frame_onwards(
-                snap_frame + 1,
-            ));
+            rb_ev.send(RollbackRequest::from_snapshot(snap_frame + 1));
             tw_status.increment_rollback_triggers();
         }
     }
 }
 
+/// Like `apply_snapshots_and_maybe_rollback`, for snapshots of components registered at runtime,
+/// which are inserted into a [`DynamicServerSnapshot`].
+pub(crate) fn apply_dynamic_snapshots_and_maybe_rollback(world: &mut World) {
+    let registered = world
+        .resource::<TimewarpRegistry>()
+        .dynamic_components
+        .clone();
+    let current_frame = world.resource::<GameClock>().frame();
+    let forced_rollback = world.resource::<TimewarpConfig>().forced_rollback();
+    let type_registry = world.resource::<AppTypeRegistry>().clone();
+    let type_registry = type_registry.read();
+    let entities = world
+        .query_filtered::<Entity, (With<DynamicServerSnapshot>, Without<NoRollback>)>()
+        .iter(world)
+        .collect::<Vec<_>>();
+    for entity in entities {
+        let mut entity_mut = world.entity_mut(entity);
+        let pending = entity_mut
+            .get_mut::<DynamicServerSnapshot>()
+            .expect("Just queried")
+            .take_pending();
+        for (type_id, snap_frame) in pending {
+            let Some(reg) = registered.iter().find(|reg| reg.type_id == type_id) else {
+                warn!("{entity:?} has a snapshot for an unregistered component: {type_id:?}");
+                continue;
+            };
+            // future snapshots wait in the DSS, like in the SS
+            if snap_frame > current_frame {
+                continue;
+            }
+            let snapshot = entity_mut
+                .get::<DynamicServerSnapshot>()
+                .and_then(|dss| dss.at_frame(type_id, snap_frame))
+                .cloned()
+                .expect("pending snapshots have a value");
+            if let Some(mut tw_status) = entity_mut.get_mut::<TimewarpStatus>() {
+                tw_status.set_snapped_at(snap_frame);
+            }
+            if snap_frame == current_frame {
+                trace!(
+                    "Inserting latecomer {entity:?} {} {snapshot:?} @ {snap_frame}",
+                    reg.type_path
+                );
+                reg.reflect
+                    .apply_or_insert(&mut entity_mut, snapshot.0.as_ref(), &type_registry);
+                world.resource_mut::<RollbackStats>().non_rollback_updates += 1;
+                entity_mut = world.entity_mut(entity);
+                continue;
+            }
+            let mut dch = entity_mut
+                .get_mut::<DynamicComponentHistory>()
+                .expect("Added along with the DSS");
+            let stored = dch.value_at_frame(type_id, snap_frame);
+            if !forced_rollback
+                && stored.is_some_and(|stored| {
+                    stored
+                        .reflect_partial_eq(snapshot.0.as_ref())
+                        .unwrap_or(false)
+                })
+            {
+                trace!("skipping rollback 🎖️ {entity:?} {snapshot:?}");
+                continue;
+            }
+            match dch.insert(type_id, reg.type_path, snap_frame, Some(snapshot.clone())) {
+                Ok(_) => (),
+                Err(TimewarpError::FrameTooOld) => {
+                    warn!("Range fault @ {snap_frame}");
+                    reg.reflect.apply_or_insert(
+                        &mut entity_mut,
+                        snapshot.0.as_ref(),
+                        &type_registry,
+                    );
+                    world.resource_mut::<RollbackStats>().range_faults += 1;
+                    entity_mut = world.entity_mut(entity);
+                    continue;
+                }
+                Err(err) => panic!("{err:?} {entity:?} {} @ {snap_frame}", reg.type_path),
+            }
+            debug!(
+                "Triggering rollback due to dynamic snapshot. {entity:?} snap_frame: {snap_frame} {}",
+                reg.type_path
+            );
+            if let Some(mut tw_status) = entity_mut.get_mut::<TimewarpStatus>() {
+                tw_status.increment_rollback_triggers();
+            }
+            world
+                .resource_mut::<Events<RollbackRequest>>()
+                .send(RollbackRequest::from_snapshot(snap_frame + 1));
+            entity_mut = world.entity_mut(entity);
+        }
+    }
+}
+
 /// Move ICAF data to the SS and add SS, because it's missing.
 ///
 /// if an ICAF was inserted, we may need to rollback.
@@ -133,10 +291,17 @@ pub(crate) fn unpack_icafs_adding_tw_components<
             ch.enable_correction_logging();
         }
         // TODO SS = yuk, sparse. use better data structure
-        let mut ss =
-            ServerSnapshot::<T>::with_capacity(timewarp_config.rollback_window as usize * 60);
-        ss.insert(icaf.frame, icaf.component.clone()).unwrap();
+        // (servers and peers never receive snapshots, so they only get the CH)
+        let ss = timewarp_config.receives_snapshots().then(|| {
+            let mut ss =
+                ServerSnapshot::<T>::with_capacity(timewarp_config.rollback_window as usize * 60);
+            ss.insert(icaf.frame, icaf.component.clone()).unwrap();
+            ss
+        });
         // (this will be applied in the ApplyComponents set next)
+        if let Some(ss) = ss {
+            commands.entity(e).insert(ss);
+        }
 
         match icaf.frame.cmp(&game_clock.frame()) {
             // if frames match, we want it inserted this frame but not rolled back
@@ -151,7 +316,7 @@ pub(crate) fn unpack_icafs_adding_tw_components<
                 }
                 commands
                     .entity(e)
-                    .insert((ch, ss, icaf.component.clone()))
+                    .insert((ch, icaf.component.clone()))
                     .remove::<InsertComponentAtFrame<T>>();
             }
             // needs insertion in the past, so request a rollback.
@@ -171,7 +336,7 @@ pub(crate) fn unpack_icafs_adding_tw_components<
                 }
                 commands
                     .entity(e)
-                    .insert((ch, ss))
+                    .insert(ch)
                     .remove::<InsertComponentAtFrame<T>>();
                 rb_ev.send(RollbackRequest::resimulate_this_frame_onwards(
                     icaf.frame + 1,
@@ -182,14 +347,14 @@ pub(crate) fn unpack_icafs_adding_tw_components<
                 // future frames. We'll store it but can't rollback to future.
                 commands
                     .entity(e)
-                    .insert((ch, ss))
+                    .insert(ch)
                     .remove::<InsertComponentAtFrame<T>>();
             }
         }
     }
 }
 
-/// Move ICAF data to the existing SS
+/// Move ICAF data to the existing CH and SS
 ///
 /// if an ICAF was inserted, we may need to rollback.
 ///
@@ -201,7 +366,7 @@ pub(crate) fn unpack_icafs_into_tw_components<
         (
             Entity,
             &InsertComponentAtFrame<T>,
-            &mut ServerSnapshot<T>,
+            Option<&mut ServerSnapshot<T>>,
             &mut ComponentHistory<T>,
             &mut TimewarpStatus,
         ),
@@ -211,11 +376,14 @@ pub(crate) fn unpack_icafs_into_tw_components<
     game_clock: Res<GameClock>,
     mut rb_ev: ResMut<Events<RollbackRequest>>,
 ) {
-    for (e, icaf, mut ss, mut ch, mut tw_status) in q.iter_mut() {
+    for (e, icaf, opt_ss, mut ch, mut tw_status) in q.iter_mut() {
         ch.insert(icaf.frame, icaf.component.clone(), &e)
             .expect("Couldn't insert ICAF to CH");
-        ss.insert(icaf.frame, icaf.component.clone())
-            .expect("Couldn't insert ICAF to SS");
+        // servers and peers have no SS
+        if let Some(mut ss) = opt_ss {
+            ss.insert(icaf.frame, icaf.component.clone())
+                .expect("Couldn't insert ICAF to SS");
+        }
 
         info!("Alive ranges for {icaf:?} = {:?}", ch.alive_ranges);
 
@@ -265,6 +433,26 @@ pub(crate) fn request_rollback_for_blueprints<T: Component + std::fmt::Debug + C
     for (entity, abaf, opt_twstatus) in q.iter_mut() {
         let snap_frame = abaf.frame;
 
+        // with the spawn state we can assemble on the frame the server did, so resimulate it.
+        if abaf.spawn_state.is_some() {
+            if snap_frame <= **game_clock {
+                debug!(
+                    "{game_clock:?} {entity:?} Requesting rollback to assemble blueprint on its exact frame {snap_frame} - {abaf:?}"
+                );
+                if let Some(mut tws) = opt_twstatus {
+                    tws.increment_rollback_triggers();
+                } else {
+                    let mut tws = TimewarpStatus::new(snap_frame);
+                    tws.increment_rollback_triggers();
+                    commands.entity(entity).insert(tws);
+                }
+                rb_ev.send(RollbackRequest::must_resimulate_this_frame_onwards(
+                    snap_frame,
+                ));
+            }
+            continue;
+        }
+
         // trace!("TESTING BP RB {abaf:?} {game_clock:?}");
         // if frames == match, we want it inserted this frame but not rolled back.
         // don't do this here, the blueprint unpacking fn does this even during rollback.
@@ -287,19 +475,130 @@ pub(crate) fn request_rollback_for_blueprints<T: Component + std::fmt::Debug + C
     }
 }
 
+/// If inputs arrived for frames we already simulated, we need to resimulate from that frame,
+/// assuming it's not too late.
+pub(crate) fn request_rollback_for_late_inputs<I: TimewarpInput>(
+    mut q: Query<(Entity, &mut InputBuffer<I>), Changed<InputBuffer<I>>>,
+    game_clock: Res<GameClock>,
+    config: Res<TimewarpConfig>,
+    mut rb_ev: ResMut<Events<RollbackRequest>>,
+    mut rb_stats: ResMut<RollbackStats>,
+) {
+    for (entity, mut input_buffer) in q.iter_mut() {
+        // don't trigger change detection again, just because we checked.
+        let input_buffer_mut = input_buffer.bypass_change_detection();
+        let new_frame = input_buffer_mut.take_oldest_new_frame();
+        let mispredicted_frame = input_buffer_mut.take_oldest_mispredicted_frame();
+        // peers simulate with predicted inputs, so only resimulate if the prediction was wrong.
+        let frame = if config.is_peer_to_peer() {
+            mispredicted_frame
+        } else {
+            new_frame
+        };
+        let Some(frame) = frame else {
+            continue;
+        };
+        // the next frame to be simulated is game_clock + 1, so inputs for that are on time.
+        if frame > **game_clock {
+            continue;
+        }
+        let frames_late = **game_clock + 1 - frame;
+        if frames_late > config.input_acceptance_window() {
+            warn!(
+                "{entity:?} {} for {frame} arrived {frames_late} frames late, not resimulating. {game_clock:?}",
+                input_buffer.type_name()
+            );
+            rb_stats.late_inputs_rejected += 1;
+            continue;
+        }
+        debug!(
+            "{entity:?} Requesting rollback for late {} @ {frame} {game_clock:?}",
+            input_buffer.type_name()
+        );
+        // inputs for a frame are applied while simulating that frame, so resimulate it.
+        rb_ev.send(RollbackRequest::resimulate_this_frame_onwards(frame));
+    }
+}
+
+/// Peer-to-peer: the confirmed frame for input type `I` is the oldest confirmed frame of any
+/// peer's input buffer.
+pub(crate) fn update_confirmed_frame<I: TimewarpInput>(
+    q: Query<&InputBuffer<I>>,
+    game_clock: Res<GameClock>,
+    config: Res<TimewarpConfig>,
+    mut confirmed_frame: ResMut<ConfirmedFrame>,
+) {
+    let frame = q
+        .iter()
+        .map(|input_buffer| input_buffer.confirmed_frame().unwrap_or_default())
+        .min();
+    confirmed_frame.set_for_input::<I>(frame);
+    if let Some(frame) = frame {
+        if **game_clock >= frame + config.rollback_window() {
+            warn!(
+                "Confirmed frame {frame} for {} is outside the rollback window, late inputs will be ignored and peers may desync. {game_clock:?}",
+                std::any::type_name::<I>()
+            );
+        }
+    }
+}
+
 /// potentially-concurrent systems request rollbacks by writing a request
 /// to the Events<RollbackRequest>, which we drain and use the smallest
 /// frame that was requested - ie, covering all requested frames.
 ///
+/// If a complete [`SnapshotBatches`] batch arrived, we know the whole world state for its frame,
+/// so only requests for newer frames matter, and we use the oldest of those.
+///
 pub(crate) fn consolidate_rollback_requests(
     mut rb_events: ResMut<Events<RollbackRequest>>,
     mut commands: Commands,
     conf: Res<TimewarpConfig>,
     game_clock: Res<GameClock>,
+    mut batches: ResMut<SnapshotBatches>,
+    mut q_status: Query<&mut TimewarpStatus>,
 ) {
+    let complete_frame = batches.newest_complete_frame();
+    let strategy = if batches.received_any() {
+        // only partial updates, so we mustn't miss any
+        RollbackConsolidationStrategy::Oldest
+    } else {
+        conf.consolidation_strategy()
+    };
+    batches.clear_received();
+    if let Some(complete_frame) = complete_frame {
+        // entities not mentioned in a complete batch are confirmed unchanged for that frame
+        for mut tw_status in q_status.iter_mut() {
+            tw_status.set_snapped_at(complete_frame);
+        }
+    }
     if rb_events.is_empty() {
         return;
     }
+    let rb_events = rb_events.drain().collect::<Vec<_>>();
+    // these are resimulated whatever the strategy picks
+    let required_frame = rb_events
+        .iter()
+        .filter(|ev| ev.is_required())
+        .map(|ev| ev.frame())
+        .min();
+    if let Some(complete_frame) = complete_frame {
+        // older snapshots are superseded, since resimulating from the complete frame covers them.
+        // requests from anything else, like late inputs or past-frame commands, still count.
+        let rb_frame = rb_events
+            .iter()
+            .filter(|ev| !ev.is_from_snapshot() || ev.frame() > complete_frame)
+            .map(|ev| ev.frame())
+            .chain(required_frame)
+            .min();
+        match rb_frame {
+            Some(rb_frame) => commands.insert_resource(Rollback::new(rb_frame, game_clock.frame())),
+            None => debug!(
+                "Rollback requests superseded by complete snapshot batch for {complete_frame}"
+            ),
+        }
+        return;
+    }
     /*
        Say the client is in PreUpdate, with clock at 100.
        There are 2 replicon packets to process which we just read from the network in this order:
@@ -317,11 +616,13 @@ pub(crate) fn consolidate_rollback_requests(
        included in the first packet (@95) but not in the second (@96).
 
        if've not really tested the second scenario yet, because replicon uses whole-world updates atm.
+
+       If the networking code uses SnapshotBatches, we don't have to guess (see above).
     */
     let mut rb_frame: FrameNumber = 0;
     // NB: a manually managed event queue, which we drain here
-    for ev in rb_events.drain() {
-        match conf.consolidation_strategy() {
+    for ev in rb_events.iter() {
+        match strategy {
             RollbackConsolidationStrategy::Newest => {
                 if rb_frame == 0 || ev.frame() > rb_frame {
                     rb_frame = ev.frame();
@@ -334,5 +635,16 @@ pub(crate) fn consolidate_rollback_requests(
             }
         }
     }
+    if let Some(required_frame) = required_frame {
+        rb_frame = rb_frame.min(required_frame);
+    }
     commands.insert_resource(Rollback::new(rb_frame, game_clock.frame()));
 }
+
+/// Servers never rollback, but the `EntityWorldMut` helpers for modifying past frames may still
+/// request one. Since nothing else drains the manually managed event queue, we do it here.
+pub(crate) fn discard_rollback_requests(mut rb_events: ResMut<Events<RollbackRequest>>) {
+    for ev in rb_events.drain() {
+        debug!("Ignoring {ev:?}, servers don't rollback");
+    }
+}
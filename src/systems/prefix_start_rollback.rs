@@ -1,5 +1,6 @@
 use crate::prelude::*;
-use bevy::prelude::*;
+use crate::registry::TimewarpRegistry;
+use bevy::{ecs::system::StaticSystemParam, prelude::*};
 use std::time::Duration;
 /*
     NOTE: Timewarp Prefix Systems run at the top of FixedUpdate:
@@ -52,6 +53,196 @@ pub(crate) fn rollback_initiated(
     game_clock.set(reset_game_clock_to);
 }
 
+/// Runs if Rollback was only just Added, before the components are rolled back.
+///
+/// [`DespawnMarker`]s in the frames we are about to resimulate are undone, including the component
+/// deaths they caused, unless they are an [`AuthoritativeDespawn`]. If the resimulation despawns
+/// the entity again, a new marker will be inserted.
+///
+/// Components registered at runtime have no alive ranges to fix, the rollback restores their
+/// value from before the despawn.
+pub(crate) fn undo_predicted_despawns(world: &mut World) {
+    let rb_start = world.resource::<Rollback>().range.start;
+    let undone = world
+        .query_filtered::<(Entity, &DespawnMarker), Without<AuthoritativeDespawn>>()
+        .iter(world)
+        .filter_map(|(entity, marker)| {
+            let frame = marker.0?;
+            (frame >= rb_start).then_some((entity, frame))
+        })
+        .collect::<Vec<_>>();
+    if undone.is_empty() {
+        return;
+    }
+    let undoers = world
+        .resource::<TimewarpRegistry>()
+        .components
+        .iter()
+        .map(|reg| reg.undo_death_at_frame)
+        .collect::<Vec<_>>();
+    for (entity, frame) in undone {
+        debug!("Undoing predicted despawn of {entity:?} @ {frame}, rollback starts at {rb_start}");
+        let mut entity_mut = world.entity_mut(entity);
+        entity_mut.remove::<DespawnMarker>();
+        for undo_death_at_frame in undoers.iter() {
+            undo_death_at_frame(&mut entity_mut, frame);
+        }
+    }
+}
+
+/// Runs if Rollback was only just Added, before the components are rolled back.
+///
+/// Entities spawned by a [`KeyedSpawner`] in the frames we are about to resimulate lose their
+/// registered components, as if they hadn't been spawned yet. Histories born since the spawn only
+/// hold the predictions we are undoing, so they go too. The resimulation will reuse the entities
+/// if it spawns the same keys again.
+pub(crate) fn unspawn_keyed_spawns(world: &mut World) {
+    let rb_start = world.resource::<Rollback>().range.start;
+    let unspawned = world
+        .resource_mut::<KeyedSpawns>()
+        .begin_resimulating_from(rb_start);
+    let registry = world.resource::<TimewarpRegistry>();
+    let unspawners = registry
+        .components
+        .iter()
+        .map(|reg| reg.unspawn_at_frame)
+        .collect::<Vec<_>>();
+    let dynamic = registry.dynamic_components.clone();
+    for (entity, spawn_frame) in unspawned.iter().copied() {
+        let Some(mut entity_mut) = world.get_entity_mut(entity) else {
+            continue;
+        };
+        debug!("Unspawning keyed {entity:?} spawned @ {spawn_frame} for rollback from {rb_start}");
+        entity_mut.remove_parent();
+        for unspawn_at_frame in unspawners.iter() {
+            unspawn_at_frame(&mut entity_mut, spawn_frame);
+        }
+        for reg in dynamic.iter() {
+            reg.unspawn_at_frame(&mut entity_mut, spawn_frame);
+        }
+        despawn_children_spawned_since(world, entity, rb_start, &unspawned);
+    }
+}
+
+/// Children spawned along with an entity we are unspawning would be spawned again by the
+/// resimulation, so they are despawned. Children that are being unspawned themselves stay
+/// attached, and children whose [`ParentHistory`] knows their parent before the rollback are
+/// left for `rollback_parents` to restore.
+fn despawn_children_spawned_since(
+    world: &mut World,
+    entity: Entity,
+    rb_start: FrameNumber,
+    unspawned: &[(Entity, FrameNumber)],
+) {
+    let Some(children) = world.get::<Children>(entity).map(|c| c.to_vec()) else {
+        return;
+    };
+    for child in children {
+        if unspawned.iter().any(|(e, _)| *e == child) {
+            continue;
+        }
+        let existed_before = world
+            .get::<ParentHistory>(child)
+            .is_some_and(|ph| ph.parent_at_frame(rb_start.saturating_sub(1)).is_some());
+        if existed_before {
+            continue;
+        }
+        debug!("Despawning {child:?}, a child of unspawned {entity:?}");
+        world.entity_mut(child).despawn_recursive();
+    }
+}
+
+/// Runs if Rollback was only just Added, before the components are rolled back.
+///
+/// Predicted blueprints assembled during the frames we are about to resimulate are given a
+/// [`PendingBlueprintAssembly`], as if they hadn't been assembled yet. They lose the blueprint
+/// component, anything its assembler added, and their registered components from the assembly
+/// frame on, keeping their histories. Children spawned since are despawned like those of keyed
+/// spawns. If the resimulation doesn't assemble them again, they are despawned along with their
+/// remaining children when the rollback completes.
+pub(crate) fn unassemble_predicted_blueprints(world: &mut World) {
+    let rb_start = world.resource::<Rollback>().range.start;
+    let unassemble = world
+        .query::<(Entity, &PredictedBlueprint)>()
+        .iter(world)
+        .filter(|(_, predicted)| predicted.frame >= rb_start)
+        .map(|(entity, predicted)| (entity, predicted.frame))
+        .collect::<Vec<_>>();
+    if unassemble.is_empty() {
+        return;
+    }
+    let registry = world.resource::<TimewarpRegistry>();
+    let unassemblers = registry.predicted_unassemblers.clone();
+    let unspawners = registry
+        .components
+        .iter()
+        .map(|reg| reg.unspawn_at_frame)
+        .collect::<Vec<_>>();
+    let dynamic = registry.dynamic_components.clone();
+    for (entity, frame) in unassemble {
+        debug!(
+            "Unassembling predicted blueprint {entity:?} @ {frame}, rollback starts at {rb_start}"
+        );
+        for unassemble in unassemblers.iter() {
+            unassemble(world, entity);
+        }
+        let mut entity_mut = world.entity_mut(entity);
+        for unspawn_at_frame in unspawners.iter() {
+            unspawn_at_frame(&mut entity_mut, frame);
+        }
+        for reg in dynamic.iter() {
+            reg.unspawn_at_frame(&mut entity_mut, frame);
+        }
+        entity_mut
+            .remove::<PredictedBlueprint>()
+            .insert(PendingBlueprintAssembly { frame });
+        despawn_children_spawned_since(world, entity, rb_start, &[]);
+    }
+}
+
+/// Runs if Rollback was only just Added, before the components are rolled back.
+/// Undoes the assembly of blueprints registered with an assembler, see `AssembledBlueprint`.
+pub(crate) fn unassemble_blueprints(world: &mut World) {
+    let rb_start = world.resource::<Rollback>().range.start;
+    let unassemblers = world.resource::<TimewarpRegistry>().unassemblers.clone();
+    for unassemble in unassemblers {
+        unassemble(world, rb_start);
+    }
+}
+
+/// Runs if Rollback was only just Added.
+/// Restores the parent each entity had at the frame we are loading values from.
+pub(crate) fn rollback_parents(
+    q: Query<(Entity, Option<&Parent>, &ParentHistory)>,
+    q_alive: Query<()>,
+    mut commands: Commands,
+    rb: Res<Rollback>,
+) {
+    let frame = rb.range.start - 1;
+    for (entity, parent, ph) in q.iter() {
+        let Some(old_parent) = ph.parent_at_frame(frame) else {
+            continue;
+        };
+        let parent = parent.map(|p| p.get());
+        if old_parent == parent {
+            continue;
+        }
+        match old_parent {
+            Some(old_parent) if q_alive.contains(old_parent) => {
+                debug!("Restoring parent of {entity:?} to {old_parent:?} @ {frame}");
+                commands.entity(entity).set_parent(old_parent);
+            }
+            Some(old_parent) => {
+                warn!("Can't restore parent of {entity:?} to despawned {old_parent:?} @ {frame}");
+            }
+            None => {
+                debug!("Removing parent of {entity:?} @ {frame}");
+                commands.entity(entity).remove_parent();
+            }
+        }
+    }
+}
+
 // for clarity when rolling back components
 #[derive(Debug)]
 enum Provenance {
@@ -61,6 +252,32 @@ enum Provenance {
     DeadThenDead,
 }
 
+/// The params of [`rollback_component`], so a bundle can restore each of its components in one system
+pub(crate) type RollbackComponentParams<T> = (
+    Res<'static, Rollback>,
+    Query<
+        'static,
+        'static,
+        (
+            Entity,
+            Option<&'static mut T>,
+            &'static ComponentHistory<T>,
+            Option<&'static ServerSnapshot<T>>,
+            Option<&'static PredictionMode>,
+            Option<&'static LocalAuthority<T>>,
+        ),
+        Without<NoRollback>,
+    >,
+    Commands<'static, 'static>,
+    Res<'static, GameClock>,
+);
+
+/// Runs if Rollback was only just Added, like `rollback_component::<T>` for every component of B,
+/// which are registered with `register_rollback_bundle`.
+pub(crate) fn rollback_bundle<B: TimewarpBundle>(params: StaticSystemParam<B::RollbackParams>) {
+    B::rollback_components(params.into_inner());
+}
+
 /// Runs if Rollback was only just Added.
 /// A rollback range starts on the frame we added new authoritative data for, so we need to
 /// restore component values to what they were at that frame, so the next frame can be resimulated.
@@ -74,14 +291,21 @@ pub(crate) fn rollback_component<T: TimewarpComponent>(
             Entity,
             Option<&mut T>,
             &ComponentHistory<T>,
-            &ServerSnapshot<T>,
+            // servers don't have a SS
+            Option<&ServerSnapshot<T>>,
+            Option<&PredictionMode>,
+            Option<&LocalAuthority<T>>,
         ),
         Without<NoRollback>,
     >,
     mut commands: Commands,
     game_clock: Res<GameClock>,
 ) {
-    for (entity, opt_comp, ch, ss) in q.iter_mut() {
+    for (entity, opt_comp, ch, ss, opt_mode, opt_authority) in q.iter_mut() {
+        // interpolated and snapped entities take their values from snapshots, not resimulation.
+        if opt_mode.is_some_and(|mode| !mode.is_predicted()) {
+            continue;
+        }
         let rollback_frame = **game_clock;
         let end_frame = rb.range.end;
 
@@ -94,9 +318,20 @@ pub(crate) fn rollback_component<T: TimewarpComponent>(
         // to the CH, because we never reached the TW postfix sets that frame.
         //
         // we always prefer the SS value if available, otherwise our own record from the CH.
-        let comp_at_rollback_frame = match ss.at_frame(rollback_frame) {
-            Some(val) => Some(val.clone()),
-            None => ch.at_frame(rollback_frame).cloned(),
+        // (unless we own the component at that frame, in which case the SS is just an echo)
+        let ss = ss.filter(|_| !opt_authority.is_some_and(|auth| auth.owns_at(rollback_frame)));
+        // and if the server says T was removed, our CH value is a misprediction.
+        let comp_at_rollback_frame = if ss.is_some_and(|ss| ss.removed_at(rollback_frame)) {
+            None
+        } else {
+            match ss.and_then(|ss| ss.at_frame(rollback_frame)) {
+                Some(val) => Some(val.clone()),
+                // the CH may still hold predicted values from after a death
+                None => ch
+                    .at_frame(rollback_frame)
+                    .filter(|_| ch.alive_at_frame(rollback_frame))
+                    .cloned(),
+            }
         };
 
         let provenance = match (
@@ -156,3 +391,40 @@ pub(crate) fn rollback_component<T: TimewarpComponent>(
         }
     }
 }
+
+/// Runs if Rollback was only just Added, like `rollback_component::<T>` for every component
+/// registered at runtime. Restores the value from the frame the clock was reset to, or removes
+/// the component if the entity didn't have it then.
+pub(crate) fn rollback_dynamic_components(world: &mut World) {
+    let registered = world
+        .resource::<TimewarpRegistry>()
+        .dynamic_components
+        .clone();
+    let frame = world.resource::<GameClock>().frame();
+    let type_registry = world.resource::<AppTypeRegistry>().clone();
+    let type_registry = type_registry.read();
+    let entities = world
+        .query_filtered::<Entity, (With<DynamicComponentHistory>, Without<NoRollback>)>()
+        .iter(world)
+        .collect::<Vec<_>>();
+    for entity in entities {
+        let mut entity_mut = world.entity_mut(entity);
+        for reg in registered.iter() {
+            let value = entity_mut
+                .get::<DynamicComponentHistory>()
+                .and_then(|dch| dch.value_at_frame(reg.type_id, frame))
+                .map(|value| value.clone_value());
+            trace!(
+                "rollback_dynamic_component {entity:?} {} @ {frame} = {value:?}",
+                reg.type_path
+            );
+            match value {
+                Some(value) => {
+                    reg.reflect
+                        .apply_or_insert(&mut entity_mut, value.as_ref(), &type_registry)
+                }
+                None => reg.reflect.remove(&mut entity_mut),
+            }
+        }
+    }
+}
@@ -1,9 +1,11 @@
-use std::marker::PhantomData;
+use std::{any::TypeId, marker::PhantomData};
 
+use crate::registry::{DynamicRegisteredComponent, RegistrationKind, TimewarpRegistry};
 use crate::systems::*;
 use bevy::{
-    ecs::system::{EntityCommand, EntityCommands},
+    ecs::system::{EntityCommand, EntityCommands, SystemParam, SystemParamItem},
     prelude::*,
+    utils::all_tuples,
 };
 
 use super::*;
@@ -23,31 +25,365 @@ where
     // Nothing to implement, since T already supports the other traits.
 }
 
+/// Trait alias for the per-frame player inputs stored in an [`InputBuffer`]
+pub trait TimewarpInput: Clone + PartialEq + std::fmt::Debug + Send + Sync + 'static {}
+
+impl<I> TimewarpInput for I where I: Clone + PartialEq + std::fmt::Debug + Send + Sync + 'static {}
+
+/// Implement for components shown with [`PredictionMode::Interpolated`].
+pub trait TimewarpInterpolate {
+    /// `t` is between 0.0 (returns self) and 1.0 (returns `to`)
+    fn interpolate(&self, to: &Self, t: f32) -> Self;
+}
+
+/// A tuple of [`TimewarpComponent`]s registered together with `register_rollback_bundle`.
+pub trait TimewarpBundle: Bundle {
+    #[doc(hidden)]
+    type RollbackParams: SystemParam + 'static;
+    #[doc(hidden)]
+    type RecordParams: SystemParam + 'static;
+    /// registers each component of the bundle for rollback. Returns false if one of them was
+    /// already registered, then they all keep their own systems, and no bundle systems are needed.
+    fn register_components(app: &mut App) -> bool;
+    #[doc(hidden)]
+    fn rollback_components(params: SystemParamItem<Self::RollbackParams>);
+    #[doc(hidden)]
+    fn record_components(params: SystemParamItem<Self::RecordParams>);
+}
+
+macro_rules! impl_timewarp_bundle {
+    ($($T:ident),*) => {
+        impl<$($T: TimewarpComponent),*> TimewarpBundle for ($($T,)*) {
+            type RollbackParams = ($(prefix_start_rollback::RollbackComponentParams<$T>,)*);
+            type RecordParams = ($(postfix_components::RecordComponentParams<$T>,)*);
+
+            fn register_components(app: &mut App) -> bool {
+                let registry = app.world.resource::<TimewarpRegistry>();
+                if $(registry.is_registered::<$T>(RegistrationKind::Rollback))||* {
+                    $(app.register_rollback::<$T>();)*
+                    return false;
+                }
+                $(register_rollback_component::<$T, false, false>(app, true);)*
+                true
+            }
+
+            #[allow(non_snake_case)]
+            fn rollback_components(params: SystemParamItem<Self::RollbackParams>) {
+                let ($($T,)*) = params;
+                $(
+                    let (rb, q, commands, game_clock) = $T;
+                    prefix_start_rollback::rollback_component::<$T>(rb, q, commands, game_clock);
+                )*
+            }
+
+            #[allow(non_snake_case)]
+            fn record_components(params: SystemParamItem<Self::RecordParams>) {
+                let ($($T,)*) = params;
+                $(
+                    let (q, game_clock, commands, opt_rb) = $T;
+                    postfix_components::record_component_history::<$T>(
+                        q, game_clock, commands, opt_rb,
+                    );
+                )*
+            }
+        }
+    };
+}
+
+all_tuples!(impl_timewarp_bundle, 1, 15, T);
+
 /// trait for registering components with the rollback system.
 pub trait TimewarpTraits {
     /// register component for rollback
     fn register_rollback<T: TimewarpComponent>(&mut self) -> &mut Self;
+    /// register every component in a tuple bundle for rollback, like `register_rollback::<T>()`
+    /// for each, except they are all restored by one system at the start of a rollback, and
+    /// recorded by one system in the `Components` postfix set, so they always stay in step.
+    /// If one of them was already registered on its own, they are all registered separately.
+    fn register_rollback_bundle<B: TimewarpBundle>(&mut self) -> &mut Self;
     /// register component for rollback, and also update a TimewarpCorrection<T> component when snapping
     fn register_rollback_with_correction_logging<T: TimewarpComponent>(&mut self) -> &mut Self;
+    /// register component for rollback, but mismatching snapshots are applied directly to the
+    /// component and its ComponentHistory without triggering a rollback.
+    /// For things like scoreboards and cosmetics, which aren't worth resimulating for.
+    fn register_rollback_snap_only<T: TimewarpComponent>(&mut self) -> &mut Self;
     /// register component for rollback with additional options
     fn register_rollback_with_options<T: TimewarpComponent, const CORRECTION_LOGGING: bool>(
         &mut self,
     ) -> &mut Self;
+    /// register a component for rollback at runtime, eg from data or a mod, by its `TypeId`.
+    /// It must be in the `AppTypeRegistry` with `#[reflect(Component)]`. Its history is stored
+    /// type-erased in a [`DynamicComponentHistory`], and snapshots go in a
+    /// [`DynamicServerSnapshot`], but otherwise it's rolled back like any registered component.
+    /// To register after startup, use [`TimewarpWorldRegistration`].
+    fn register_rollback_by_type_id(&mut self, type_id: TypeId) -> &mut Self;
+    /// like `register_rollback_by_type_id`, using the type path, eg `"my_mod::Mana"`
+    fn register_rollback_by_type_path(&mut self, type_path: &str) -> &mut Self;
     fn register_blueprint<T: TimewarpComponent>(&mut self) -> &mut Self;
+    /// like `register_blueprint`, but timewarp runs `assembler` when unwrapping the blueprint,
+    /// normally and during rollback, and inserts the bundle it returns. What was added is recorded
+    /// in an [`AssembledBlueprint<T>`], and the bundle is removed again if a rollback resimulates
+    /// the assembly frame.
+    fn register_blueprint_with_assembler<T: TimewarpComponent, B: Bundle>(
+        &mut self,
+        assembler: fn(&mut EntityWorldMut, &T) -> B,
+    ) -> &mut Self;
+    /// record the `Parent` of timewarp entities in a [`ParentHistory`], and restore it when
+    /// rolling back, so re-parenting during the rollback window is undone correctly.
+    fn register_rollback_hierarchy(&mut self) -> &mut Self;
+    /// set the value of T from its [`ServerSnapshot`] for entities with
+    /// [`PredictionMode::Interpolated`]. T must also be registered for rollback.
+    fn register_interpolation<T: TimewarpComponent + TimewarpInterpolate>(&mut self) -> &mut Self;
+    /// register an input type, stored per-player in an [`InputBuffer<I>`].
+    /// On servers with a `late_input_window`, and for peers, inputs inserted for frames we
+    /// already simulated will trigger a rollback, if they are within the acceptance window.
+    /// See [`TimewarpConfig::input_acceptance_window`].
+    /// In peer-to-peer and lockstep modes, this also tracks the [`ConfirmedFrame`] for these inputs.
+    fn register_input<I: TimewarpInput>(&mut self) -> &mut Self;
+}
+
+fn add_blueprint_rollback_requests<'a, T: TimewarpComponent>(
+    app: &'a mut App,
+    config: &TimewarpConfig,
+) -> &'a mut App {
+    // servers are authoritative, so past blueprints are unwrapped right away, see `unwrap_now`.
+    if config.is_server() {
+        return app;
+    }
+    app.add_systems(
+        config.schedule(),
+        prefix_not_in_rollback::request_rollback_for_blueprints::<T>
+            .before(prefix_not_in_rollback::consolidate_rollback_requests)
+            .in_set(TimewarpPrefixSet::NotInRollback),
+    )
+}
+
+/// Registers T for rollback. SNAP_ONLY components never trigger a rollback, mismatching
+/// snapshots are applied directly, see `register_rollback_snap_only`.
+/// `bundled` components are restored and recorded by their bundle's systems instead,
+/// see `add_bundle_systems`.
+fn register_rollback_component<
+    T: TimewarpComponent,
+    const CORRECTION_LOGGING: bool,
+    const SNAP_ONLY: bool,
+>(
+    app: &mut App,
+    bundled: bool,
+) -> &mut App {
+    let config = app
+        .world
+        .get_resource::<TimewarpConfig>()
+        .expect("TimewarpConfig resource expected");
+    let config = config.clone();
+    let schedule = config.schedule();
+
+    let mut registry = app.world.resource_mut::<TimewarpRegistry>();
+    if !registry.add(RegisteredType {
+        correction_logging: CORRECTION_LOGGING,
+        snap_only: SNAP_ONLY,
+        ..RegisteredType::new(
+            TypeId::of::<T>(),
+            std::any::type_name::<T>(),
+            RegistrationKind::Rollback,
+        )
+    }) {
+        return app;
+    }
+    registry.register::<T>();
+
+    if !config.rollback_enabled() {
+        // servers just record history, and cleanup despawns.
+        app.add_systems(
+            schedule,
+            prefix_first::record_component_death::<T>.in_set(TimewarpPrefixSet::First),
+        );
+        app.add_systems(
+            schedule,
+            (
+                postfix_components::remove_components_from_despawning_entities::<T>,
+                postfix_components::add_timewarp_components::<T, CORRECTION_LOGGING>,
+            )
+                .in_set(TimewarpPostfixSet::Components),
+        );
+        if !bundled {
+            add_record_system::<T>(app, &config);
+        }
+        return app;
+    }
+
+    /*
+           Prefix Systems
+    */
+    if CORRECTION_LOGGING {
+        app.add_systems(
+            schedule,
+            prefix_first::enable_error_correction_for_new_component_histories::<T>
+                .in_set(TimewarpPrefixSet::First),
+        );
+    }
+    app.add_systems(
+        schedule,
+        prefix_first::record_component_death::<T>
+            .run_if(not(resource_exists::<Rollback>))
+            .in_set(TimewarpPrefixSet::First),
+    );
+    app.add_systems(
+        schedule,
+        (prefix_in_rollback::rebirth_components_during_rollback::<T>,)
+            .in_set(TimewarpPrefixSet::InRollback)
+            // this after stops an edge case where [systems::prefix::check_for_rollback_completion] uses
+            // `commands.remove_resource::<Rollback>()` and `apply_deferred` is `.chain()`ed after it,
+            // removing the resource before this system runs
+            .before(systems::prefix_in_rollback::check_for_rollback_completion),
+    );
+    // this may result in a Rollback resource being inserted.
+    app.add_systems(
+        schedule,
+        (
+            prefix_not_in_rollback::unpack_icafs_into_tw_components::<T, CORRECTION_LOGGING>,
+            prefix_not_in_rollback::unpack_icafs_adding_tw_components::<T, CORRECTION_LOGGING>,
+        )
+            .before(prefix_not_in_rollback::consolidate_rollback_requests)
+            .in_set(TimewarpPrefixSet::NotInRollback),
+    );
+    // (servers are authoritative, and peers rollback based on inputs, so no snapshots)
+    if config.receives_snapshots() {
+        app.add_systems(
+            schedule,
+            prefix_not_in_rollback::apply_snapshots_and_maybe_rollback::<T, SNAP_ONLY>
+                .before(prefix_not_in_rollback::consolidate_rollback_requests)
+                .in_set(TimewarpPrefixSet::NotInRollback),
+        );
+    }
+    if !bundled {
+        app.add_systems(
+            schedule,
+            (prefix_start_rollback::rollback_component::<T>,)
+                .in_set(TimewarpPrefixSet::StartRollback)
+                .after(prefix_start_rollback::unassemble_blueprints),
+        );
+    }
+
+    /*
+           Postfix Systems
+    */
+    app.add_systems(
+        schedule,
+        (
+            postfix_components::remove_components_from_despawning_entities::<T>,
+            postfix_components::add_timewarp_components::<T, CORRECTION_LOGGING>,
+        )
+            .in_set(TimewarpPostfixSet::Components),
+    );
+    if !bundled {
+        add_record_system::<T>(app, &config);
+    }
+    if config.receives_snapshots() {
+        app.add_systems(
+            schedule,
+            postfix_components::apply_snapshots_during_rollback::<T>
+                .run_if(resource_exists::<Rollback>.and_then(snapshot_batches_in_use))
+                .before(postfix_components::RecordComponentHistory)
+                .in_set(TimewarpPostfixSet::Components),
+        );
+    }
+    if config.is_server() {
+        // must run before the resimulated value overwrites the old one in the CH
+        app.add_systems(
+            schedule,
+            postfix_components::record_server_corrections::<T>
+                .run_if(resource_exists::<Rollback>)
+                .before(postfix_components::RecordComponentHistory)
+                .in_set(TimewarpPostfixSet::Components),
+        );
+    }
+    app.add_systems(
+        schedule,
+        (
+            postfix_in_rollback::rekill_components_during_rollback::<T>,
+            postfix_in_rollback::clear_removed_components_queue::<T>,
+        )
+            .in_set(TimewarpPostfixSet::InRollback),
+    )
+}
+
+fn add_record_system<T: TimewarpComponent>(app: &mut App, config: &TimewarpConfig) {
+    app.add_systems(
+        config.schedule(),
+        postfix_components::record_component_history::<T>
+            .in_set(postfix_components::RecordComponentHistory)
+            .in_set(TimewarpPostfixSet::Components),
+    );
+}
+
+/// One system restores all of B's components at the start of a rollback, and one records them.
+fn add_bundle_systems<B: TimewarpBundle>(app: &mut App) -> &mut App {
+    let config = app
+        .world
+        .get_resource::<TimewarpConfig>()
+        .expect("TimewarpConfig resource expected")
+        .clone();
+    if config.rollback_enabled() {
+        app.add_systems(
+            config.schedule(),
+            prefix_start_rollback::rollback_bundle::<B>
+                .in_set(TimewarpPrefixSet::StartRollback)
+                .after(prefix_start_rollback::unassemble_blueprints),
+        );
+    }
+    app.add_systems(
+        config.schedule(),
+        postfix_components::record_bundle_history::<B>
+            .in_set(postfix_components::RecordComponentHistory)
+            .in_set(TimewarpPostfixSet::Components),
+    )
 }
 
 impl TimewarpTraits for App {
     fn register_rollback<T: TimewarpComponent>(&mut self) -> &mut Self {
         self.register_rollback_with_options::<T, false>()
     }
+    fn register_rollback_bundle<B: TimewarpBundle>(&mut self) -> &mut Self {
+        if B::register_components(self) {
+            add_bundle_systems::<B>(self);
+        }
+        self
+    }
     fn register_rollback_with_correction_logging<T: TimewarpComponent>(&mut self) -> &mut Self {
         self.register_rollback_with_options::<T, true>()
     }
+    fn register_rollback_snap_only<T: TimewarpComponent>(&mut self) -> &mut Self {
+        register_rollback_component::<T, false, true>(self, false)
+    }
+    fn register_rollback_by_type_id(&mut self, type_id: TypeId) -> &mut Self {
+        self.world.register_rollback_by_type_id(type_id);
+        self
+    }
+    fn register_rollback_by_type_path(&mut self, type_path: &str) -> &mut Self {
+        self.world.register_rollback_by_type_path(type_path);
+        self
+    }
     fn register_blueprint<T: TimewarpComponent>(&mut self) -> &mut Self {
+        if !self
+            .world
+            .resource_mut::<TimewarpRegistry>()
+            .add(RegisteredType::new(
+                TypeId::of::<T>(),
+                std::any::type_name::<T>(),
+                RegistrationKind::Blueprint,
+            ))
+        {
+            return self;
+        }
+        self.world
+            .resource_mut::<TimewarpRegistry>()
+            .predicted_unassemblers
+            .push(prefix_blueprints::unassemble_predicted_blueprint::<T>);
         let config = self
             .world
             .get_resource::<TimewarpConfig>()
             .expect("TimewarpConfig resource expected");
+        let config = config.clone();
         let schedule = config.schedule();
         // when we rollback, unpack anything wrapped up for this frame.
         // this handles the case where we are rolling back because of a wrapped blueprint, and
@@ -61,84 +397,119 @@ impl TimewarpTraits for App {
             )
                 .in_set(TimewarpPrefixSet::UnwrapBlueprints),
         );
-        self.add_systems(
-            schedule,
-            prefix_not_in_rollback::request_rollback_for_blueprints::<T>
-                .before(prefix_not_in_rollback::consolidate_rollback_requests)
-                .in_set(TimewarpPrefixSet::NotInRollback),
-        )
+        add_blueprint_rollback_requests::<T>(self, &config)
     }
-    fn register_rollback_with_options<T: TimewarpComponent, const CORRECTION_LOGGING: bool>(
+    fn register_blueprint_with_assembler<T: TimewarpComponent, B: Bundle>(
         &mut self,
+        assembler: fn(&mut EntityWorldMut, &T) -> B,
     ) -> &mut Self {
+        if !self
+            .world
+            .resource_mut::<TimewarpRegistry>()
+            .add(RegisteredType::new(
+                TypeId::of::<T>(),
+                std::any::type_name::<T>(),
+                RegistrationKind::BlueprintWithAssembler,
+            ))
+        {
+            return self;
+        }
         let config = self
             .world
             .get_resource::<TimewarpConfig>()
             .expect("TimewarpConfig resource expected");
+        let config = config.clone();
         let schedule = config.schedule();
-
-        /*
-               Prefix Systems
-        */
-        if CORRECTION_LOGGING {
-            self.add_systems(
-                schedule,
-                prefix_first::enable_error_correction_for_new_component_histories::<T>
-                    .in_set(TimewarpPrefixSet::First),
-            );
-        }
-        self.add_systems(
-            schedule,
-            prefix_first::record_component_death::<T>
-                .run_if(not(resource_exists::<Rollback>))
-                .in_set(TimewarpPrefixSet::First),
-        );
+        self.insert_resource(prefix_blueprints::BlueprintAssembler(assembler));
+        let mut registry = self.world.resource_mut::<TimewarpRegistry>();
+        registry
+            .unassemblers
+            .push(prefix_blueprints::unassemble_blueprints_of_type::<T>);
+        registry
+            .predicted_unassemblers
+            .push(prefix_blueprints::unassemble_predicted_blueprint::<T>);
         self.add_systems(
             schedule,
-            (prefix_in_rollback::rebirth_components_during_rollback::<T>,)
-                .in_set(TimewarpPrefixSet::InRollback)
-                // this after stops an edge case where [systems::prefix::check_for_rollback_completion] uses
-                // `commands.remove_resource::<Rollback>()` and `apply_deferred` is `.chain()`ed after it,
-                // removing the resource before this system runs
-                .before(systems::prefix_in_rollback::check_for_rollback_completion),
+            prefix_blueprints::unwrap_and_assemble_blueprints::<T, B>
+                .in_set(TimewarpPrefixSet::UnwrapBlueprints),
         );
-        // this may result in a Rollback resource being inserted.
+        add_blueprint_rollback_requests::<T>(self, &config)
+    }
+    fn register_rollback_hierarchy(&mut self) -> &mut Self {
+        let config = self
+            .world
+            .get_resource::<TimewarpConfig>()
+            .expect("TimewarpConfig resource expected");
+        let config = config.clone();
+        let schedule = config.schedule();
         self.add_systems(
             schedule,
             (
-                prefix_not_in_rollback::unpack_icafs_into_tw_components::<T, CORRECTION_LOGGING>,
-                prefix_not_in_rollback::unpack_icafs_adding_tw_components::<T, CORRECTION_LOGGING>,
-                prefix_not_in_rollback::apply_snapshots_and_maybe_rollback::<T>,
+                postfix_components::record_parent_history,
+                postfix_components::add_parent_history,
             )
-                .before(prefix_not_in_rollback::consolidate_rollback_requests)
-                .in_set(TimewarpPrefixSet::NotInRollback),
+                .in_set(TimewarpPostfixSet::Components),
         );
+        if !config.rollback_enabled() {
+            return self;
+        }
         self.add_systems(
             schedule,
-            (prefix_start_rollback::rollback_component::<T>,)
+            prefix_start_rollback::rollback_parents
                 .in_set(TimewarpPrefixSet::StartRollback)
-                .after(prefix_start_rollback::rollback_initiated),
-        );
-
-        /*
-               Postfix Systems
-        */
+                .after(prefix_start_rollback::unassemble_blueprints),
+        )
+    }
+    fn register_interpolation<T: TimewarpComponent + TimewarpInterpolate>(&mut self) -> &mut Self {
+        let config = self
+            .world
+            .get_resource::<TimewarpConfig>()
+            .expect("TimewarpConfig resource expected");
+        let schedule = config.schedule();
+        // overwrites whatever the game logic did to T, before we record it
         self.add_systems(
             schedule,
-            (
-                postfix_components::remove_components_from_despawning_entities::<T>,
-                postfix_components::record_component_history::<T>,
-                postfix_components::add_timewarp_components::<T, CORRECTION_LOGGING>,
-            )
+            postfix_components::interpolate_components::<T>
+                .run_if(not(resource_exists::<Rollback>))
+                .before(postfix_components::RecordComponentHistory)
                 .in_set(TimewarpPostfixSet::Components),
-        );
+        )
+    }
+    fn register_rollback_with_options<T: TimewarpComponent, const CORRECTION_LOGGING: bool>(
+        &mut self,
+    ) -> &mut Self {
+        register_rollback_component::<T, CORRECTION_LOGGING, false>(self, false)
+    }
+    fn register_input<I: TimewarpInput>(&mut self) -> &mut Self {
+        let config = self
+            .world
+            .get_resource::<TimewarpConfig>()
+            .expect("TimewarpConfig resource expected");
+        let schedule = config.schedule();
+        let accepts_late_inputs = config.input_acceptance_window() > 0;
+        if config.is_peer_to_peer() {
+            self.add_systems(
+                schedule,
+                prefix_not_in_rollback::update_confirmed_frame::<I>
+                    .in_set(TimewarpPrefixSet::First),
+            );
+        } else if config.is_lockstep() {
+            // must be up to date before we decide if we're stalling this tick
+            self.add_systems(
+                schedule,
+                prefix_not_in_rollback::update_confirmed_frame::<I>
+                    .before(prefix_first::update_lockstep_stats),
+            );
+        }
+        // clients are corrected by server snapshots, and lockstep never rolls back.
+        if !accepts_late_inputs {
+            return self;
+        }
         self.add_systems(
             schedule,
-            (
-                postfix_in_rollback::rekill_components_during_rollback::<T>,
-                postfix_in_rollback::clear_removed_components_queue::<T>,
-            )
-                .in_set(TimewarpPostfixSet::InRollback),
+            prefix_not_in_rollback::request_rollback_for_late_inputs::<I>
+                .before(prefix_not_in_rollback::consolidate_rollback_requests)
+                .in_set(TimewarpPrefixSet::NotInRollback),
         )
     }
 }
@@ -150,14 +521,215 @@ pub enum InsertComponentResult {
     ComponentsAdded,
 }
 
-trait TimewarpCommands {
-    fn remove_component_at_end_of_frame<T: TimewarpComponent>(&mut self, frame: FrameNumber);
+/// Registering components at runtime from a `World`, for content loaded after startup, eg by an
+/// exclusive system, when the `App` isn't available. Same as the [`TimewarpTraits`] methods.
+pub trait TimewarpWorldRegistration {
+    /// see [`TimewarpTraits::register_rollback_by_type_id`]
+    fn register_rollback_by_type_id(&mut self, type_id: TypeId) -> &mut Self;
+    /// see [`TimewarpTraits::register_rollback_by_type_path`]
+    fn register_rollback_by_type_path(&mut self, type_path: &str) -> &mut Self;
+}
+
+impl TimewarpWorldRegistration for World {
+    fn register_rollback_by_type_id(&mut self, type_id: TypeId) -> &mut Self {
+        let type_registry = self.resource::<AppTypeRegistry>().clone();
+        let type_registry = type_registry.read();
+        let Some(registration) = type_registry.get(type_id) else {
+            error!("Can't register {type_id:?} for rollback, it's not in the AppTypeRegistry");
+            return self;
+        };
+        let type_path = registration.type_info().type_path();
+        let Some(reflect) = registration.data::<ReflectComponent>() else {
+            error!("Can't register {type_path} for rollback, it doesn't #[reflect(Component)]");
+            return self;
+        };
+        let mut registry = self.resource_mut::<TimewarpRegistry>();
+        if !registry.add(RegisteredType::new(
+            type_id,
+            type_path,
+            RegistrationKind::DynamicRollback,
+        )) {
+            return self;
+        }
+        // the systems handling these were added by the plugin, and start running now
+        registry
+            .dynamic_components
+            .push(DynamicRegisteredComponent {
+                type_id,
+                type_path,
+                reflect: reflect.clone(),
+            });
+        self
+    }
+    fn register_rollback_by_type_path(&mut self, type_path: &str) -> &mut Self {
+        let type_id = self
+            .resource::<AppTypeRegistry>()
+            .read()
+            .get_with_type_path(type_path)
+            .map(|registration| registration.type_id());
+        let Some(type_id) = type_id else {
+            error!("Can't register {type_path} for rollback, it's not in the AppTypeRegistry");
+            return self;
+        };
+        self.register_rollback_by_type_id(type_id)
+    }
+}
+
+/// Past-frame operations for ordinary systems, as an extension to `EntityCommands`.
+///
+/// Errors are sent as [`TimewarpCommandError`] events when the command is applied.
+pub trait TimewarpCommands {
+    /// Inserts `component` at `frame`, like [`TimewarpEntityMutTraits::insert_component_at_frame`]
+    fn insert_at_frame<T: TimewarpComponent>(
+        &mut self,
+        frame: FrameNumber,
+        component: T,
+    ) -> &mut Self;
+    /// Removes the component at the end of `frame`, rolling back if `frame` is in the past.
+    /// Past frames must be within the rollback window, and the entity must have a
+    /// `ComponentHistory<T>`.
+    fn remove_at_frame<T: TimewarpComponent>(&mut self, frame: FrameNumber) -> &mut Self;
+    /// See [`TimewarpEntityMutTraits::despawn_at_frame`]
+    fn despawn_at_frame(&mut self, frame: FrameNumber) -> &mut Self;
+    /// Inserts an [`AssembleBlueprintAtFrame`], so the blueprint is assembled at `frame`
+    fn assemble_blueprint_at_frame<T: Component + std::fmt::Debug + Clone>(
+        &mut self,
+        frame: FrameNumber,
+        blueprint: T,
+    ) -> &mut Self;
 }
 
 impl TimewarpCommands for EntityCommands<'_> {
-    fn remove_component_at_end_of_frame<T: TimewarpComponent>(&mut self, frame: FrameNumber) {
-        self.add(RemoveComponentAtFrame::<T>::new(frame));
+    fn insert_at_frame<T: TimewarpComponent>(
+        &mut self,
+        frame: FrameNumber,
+        component: T,
+    ) -> &mut Self {
+        self.add(move |entity: Entity, world: &mut World| {
+            let result = match world.get_entity_mut(entity) {
+                Some(mut entity_mut) => entity_mut.insert_component_at_frame(frame, &component),
+                None => Err(TimewarpError::NoSuchEntity),
+            };
+            if let Err(error) = result {
+                send_command_error(world, entity, frame, error);
+            }
+        })
+    }
+
+    fn remove_at_frame<T: TimewarpComponent>(&mut self, frame: FrameNumber) -> &mut Self {
+        self.add(RemoveComponentAtFrame::<T>::new(frame))
+    }
+
+    fn despawn_at_frame(&mut self, frame: FrameNumber) -> &mut Self {
+        self.add(move |entity: Entity, world: &mut World| {
+            let result = match world.get_entity_mut(entity) {
+                Some(mut entity_mut) => entity_mut.despawn_at_frame(frame),
+                None => Err(TimewarpError::NoSuchEntity),
+            };
+            if let Err(error) = result {
+                send_command_error(world, entity, frame, error);
+            }
+        })
     }
+
+    fn assemble_blueprint_at_frame<T: Component + std::fmt::Debug + Clone>(
+        &mut self,
+        frame: FrameNumber,
+        blueprint: T,
+    ) -> &mut Self {
+        self.add(move |entity: Entity, world: &mut World| {
+            let current_frame = world.resource::<GameClock>().frame();
+            let rollback_window = world.resource::<TimewarpConfig>().rollback_window();
+            if current_frame.saturating_sub(frame) >= rollback_window {
+                send_command_error(world, entity, frame, TimewarpError::FrameTooOld);
+                return;
+            }
+            let Some(mut entity_mut) = world.get_entity_mut(entity) else {
+                send_command_error(world, entity, frame, TimewarpError::NoSuchEntity);
+                return;
+            };
+            entity_mut.insert(AssembleBlueprintAtFrame::new(frame, blueprint));
+        })
+    }
+}
+
+/// Spawning in the past, as an extension to `Commands`.
+pub trait TimewarpSpawnCommands {
+    /// Spawns an entity that has existed since `frame`, with `bundle`.
+    ///
+    /// Registered components in the bundle get a history starting at `frame`, and a single
+    /// rollback resimulates the frames since. Other components are only there from now on.
+    /// If `frame` is outside the rollback window, a [`TimewarpCommandError`] is sent, and the
+    /// bundle is spawned at the current frame.
+    fn spawn_at_frame<B: Bundle>(&mut self, frame: FrameNumber, bundle: B) -> EntityCommands<'_>;
+}
+
+impl TimewarpSpawnCommands for Commands<'_, '_> {
+    fn spawn_at_frame<B: Bundle>(&mut self, frame: FrameNumber, bundle: B) -> EntityCommands<'_> {
+        let mut ec = self.spawn(bundle);
+        ec.add(move |entity: Entity, world: &mut World| {
+            spawn_entity_at_frame(world, entity, frame);
+        });
+        ec
+    }
+}
+
+fn spawn_entity_at_frame(world: &mut World, entity: Entity, frame: FrameNumber) {
+    let current_frame = world.resource::<GameClock>().frame();
+    let rollback_window = world.resource::<TimewarpConfig>().rollback_window();
+    if frame > current_frame {
+        send_command_error(world, entity, frame, TimewarpError::FrameInFuture);
+        return;
+    }
+    if frame == current_frame {
+        // spawned just in time, nothing to rollback
+        return;
+    }
+    // same limit as insert_component_at_frame
+    if frame <= current_frame.saturating_sub(rollback_window - 1) {
+        send_command_error(world, entity, frame, TimewarpError::FrameTooOldSnapped);
+        return;
+    }
+    let registry = world.resource::<TimewarpRegistry>();
+    let spawners = registry
+        .components
+        .iter()
+        .map(|reg| reg.spawn_at_frame)
+        .collect::<Vec<_>>();
+    let dynamic = registry.dynamic_components.clone();
+    let Some(mut entity_mut) = world.get_entity_mut(entity) else {
+        send_command_error(world, entity, frame, TimewarpError::NoSuchEntity);
+        return;
+    };
+    let mut spawned = false;
+    for spawn_at_frame in spawners {
+        spawned |= spawn_at_frame(&mut entity_mut, frame);
+    }
+    for reg in dynamic.iter() {
+        spawned |= reg.spawn_at_frame(&mut entity_mut, frame);
+    }
+    if !spawned {
+        return;
+    }
+    if let Some(mut tw_status) = entity_mut.get_mut::<TimewarpStatus>() {
+        tw_status.increment_rollback_triggers();
+        tw_status.set_snapped_at(frame);
+    }
+    debug!("Requesting rollback for {entity:?} spawned at {frame}");
+    world
+        .resource_mut::<Events<RollbackRequest>>()
+        .send(RollbackRequest::resimulate_this_frame_onwards(frame + 1));
+}
+
+fn send_command_error(world: &mut World, entity: Entity, frame: FrameNumber, error: TimewarpError) {
+    warn!("Timewarp command failed for {entity:?} @ {frame}: {error:?}");
+    world
+        .resource_mut::<Events<TimewarpCommandError>>()
+        .send(TimewarpCommandError {
+            entity,
+            frame,
+            error,
+        });
 }
 
 pub struct RemoveComponentAtFrame<T: TimewarpComponent> {
@@ -166,10 +738,10 @@ pub struct RemoveComponentAtFrame<T: TimewarpComponent> {
 }
 
 impl<T: TimewarpComponent> RemoveComponentAtFrame<T> {
-    fn new(frame: FrameNumber) -> Self {
+    pub fn new(frame: FrameNumber) -> Self {
         Self {
             frame,
-            _phantom: PhantomData::default(),
+            _phantom: PhantomData,
         }
     }
 }
@@ -179,7 +751,25 @@ where
     Self: Sized,
 {
     fn apply(self, id: Entity, world: &mut World) {
-        let mut ent_cmd = world.entity_mut(id);
+        let current_frame = world.resource::<GameClock>().frame();
+        let rollback_window = world.resource::<TimewarpConfig>().rollback_window();
+        if self.frame > current_frame {
+            send_command_error(world, id, self.frame, TimewarpError::FrameInFuture);
+            return;
+        }
+        // same limit as despawn_at_frame
+        if current_frame - self.frame >= rollback_window {
+            send_command_error(world, id, self.frame, TimewarpError::FrameTooOld);
+            return;
+        }
+        let Some(mut ent_cmd) = world.get_entity_mut(id) else {
+            send_command_error(world, id, self.frame, TimewarpError::NoSuchEntity);
+            return;
+        };
+        if self.frame < current_frame && !ent_cmd.contains::<ComponentHistory<T>>() {
+            send_command_error(world, id, self.frame, TimewarpError::NoComponentHistory);
+            return;
+        }
         ent_cmd.remove_component_at_end_of_frame::<T>(self.frame);
     }
 }
@@ -189,6 +779,10 @@ where
 pub trait TimewarpEntityMutTraits {
     /// removes component at past frame
     fn remove_component_at_end_of_frame<T: TimewarpComponent>(&mut self, frame: FrameNumber);
+    /// Despawns the entity as of a past (or the current) frame: all registered components are
+    /// dead at `frame`, a rollback is requested if needed, and the actual despawn happens
+    /// `rollback_window` frames after `frame`, like a [`DespawnMarker`].
+    fn despawn_at_frame(&mut self, frame: FrameNumber) -> Result<(), TimewarpError>;
     /// For inserting a component into a specific frame.
     /// Timewarp systems will insert into the entity at the correct point.
     fn insert_component_at_frame<T: TimewarpComponent>(
@@ -216,8 +810,17 @@ impl TimewarpEntityMutTraits for EntityWorldMut<'_> {
             return;
         }
 
+        if let Some(mut ss) = self.get_mut::<ServerSnapshot<T>>() {
+            ss.insert_removal(frame);
+        }
         if let Some(mut ch) = self.get_mut::<ComponentHistory<T>>() {
             ch.report_death_at_frame(frame);
+            let is_server = self.world().resource::<TimewarpConfig>().is_server();
+            if is_server {
+                // servers never rollback, so remove it now to match the history.
+                self.remove::<T>();
+                return;
+            }
             self.world_scope(|world: &mut World| {
                 let mut rb_ev = world.resource_mut::<Events<RollbackRequest>>();
                 warn!("Requesting Rollback due to remove_component_at_frame, {frame}");
@@ -226,6 +829,69 @@ impl TimewarpEntityMutTraits for EntityWorldMut<'_> {
         }
     }
 
+    fn despawn_at_frame(&mut self, frame: FrameNumber) -> Result<(), TimewarpError> {
+        let game_clock = self
+            .world()
+            .get_resource::<GameClock>()
+            .expect("GameClock should be present");
+        let current_frame = game_clock.frame();
+        let tw_config = self
+            .world()
+            .get_resource::<TimewarpConfig>()
+            .expect("TimewarpConfig resource missing");
+        if frame > current_frame {
+            return Err(TimewarpError::FrameInFuture);
+        }
+        if current_frame - frame >= tw_config.rollback_window() {
+            warn!("despawn_at_frame too old {frame} / {game_clock:?}");
+            return Err(TimewarpError::FrameTooOld);
+        }
+        let registry = self.world().resource::<TimewarpRegistry>();
+        let killers = registry
+            .components
+            .iter()
+            .map(|reg| reg.kill_at_frame)
+            .collect::<Vec<_>>();
+        let dynamic = registry.dynamic_components.clone();
+        for kill_at_frame in killers.iter() {
+            kill_at_frame(self, frame);
+        }
+        for reg in dynamic.iter() {
+            reg.kill_at_frame(self, frame);
+        }
+        // authoritative, so a rollback mustn't undo it
+        self.insert((DespawnMarker::for_frame(frame), AuthoritativeDespawn));
+        // children die with their parent
+        let entity = self.id();
+        self.world_scope(|world: &mut World| {
+            let mut descendants = world
+                .get::<Children>(entity)
+                .map(|c| c.to_vec())
+                .unwrap_or_default();
+            while let Some(child) = descendants.pop() {
+                if let Some(children) = world.get::<Children>(child) {
+                    descendants.extend(children.iter());
+                }
+                let mut child_mut = world.entity_mut(child);
+                for kill_at_frame in killers.iter() {
+                    kill_at_frame(&mut child_mut, frame);
+                }
+                for reg in dynamic.iter() {
+                    reg.kill_at_frame(&mut child_mut, frame);
+                }
+                child_mut.insert((DespawnMarker::for_frame(frame), AuthoritativeDespawn));
+            }
+        });
+        if frame < current_frame {
+            self.world_scope(|world: &mut World| {
+                let mut rb_ev = world.resource_mut::<Events<RollbackRequest>>();
+                debug!("Requesting Rollback due to despawn_at_frame, {frame}");
+                rb_ev.send(RollbackRequest::resimulate_this_frame_onwards(frame));
+            });
+        }
+        Ok(())
+    }
+
     fn insert_component_at_frame_or_snap<T: TimewarpComponent>(
         &mut self,
         frame: FrameNumber,
@@ -0,0 +1,187 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+#[derive(Clone, Debug, Component, PartialEq)]
+struct ShipBlueprint {
+    health: i32,
+}
+
+/// not replicated, only added by blueprint assembly
+#[derive(Component, Debug)]
+struct Collider;
+
+/// frames simulated while the ship had a collider
+#[derive(Resource, Default)]
+struct ColliderFrames(Vec<FrameNumber>);
+
+static ASSEMBLIES: AtomicU32 = AtomicU32::new(0);
+
+fn assemble_ship(_entity: &mut EntityWorldMut, bp: &ShipBlueprint) -> (Enemy, Collider) {
+    ASSEMBLIES.fetch_add(1, Ordering::SeqCst);
+    (Enemy { health: bp.health }, Collider)
+}
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<&mut Enemy>) {
+    for mut enemy in q.iter_mut() {
+        enemy.health -= 1;
+    }
+}
+
+fn record_colliders(
+    q: Query<(), With<Collider>>,
+    mut frames: ResMut<ColliderFrames>,
+    game_clock: Res<GameClock>,
+) {
+    if !q.is_empty() {
+        frames.0.push(game_clock.frame());
+    }
+}
+
+#[test]
+fn assembler_runs_and_is_undone_by_rollback() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_blueprint_with_assembler::<ShipBlueprint, _>(assemble_ship);
+    app.init_resource::<ColliderFrames>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, record_colliders)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health: 10 }).id();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    // the server spawned a ship during frame 3, so we assemble it while resimulating frame 4
+    let ship = app
+        .world
+        .spawn(AssembleBlueprintAtFrame::new(
+            3,
+            ShipBlueprint { health: 100 },
+        ))
+        .id();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(ASSEMBLIES.load(Ordering::SeqCst), 1);
+    let assembled = app
+        .world
+        .get::<AssembledBlueprint<ShipBlueprint>>(ship)
+        .unwrap();
+    assert_eq!(assembled.assembled_frame(), 4);
+    assert_eq!(assembled.components().len(), 2);
+    assert!(app.world.get::<Collider>(ship).is_some());
+    assert_eq!(app.world.get::<Enemy>(ship).unwrap().health, 97);
+    assert_eq!(app.world.resource::<ColliderFrames>().0, vec![4, 5, 6]);
+
+    // a rollback to before the assembly frame
+    app.world.resource_mut::<ColliderFrames>().0.clear();
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 50 })
+        .unwrap();
+
+    tick(&mut app); // frame 7
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 2);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 3);
+    // the collider was removed for the resimulation of frame 3, and reassembled for frame 4
+    assert_eq!(ASSEMBLIES.load(Ordering::SeqCst), 2);
+    assert_eq!(app.world.resource::<ColliderFrames>().0, vec![4, 5, 6, 7]);
+    assert_eq!(
+        app.world
+            .get::<AssembledBlueprint<ShipBlueprint>>(ship)
+            .unwrap()
+            .assembled_frame(),
+        4
+    );
+    assert_eq!(app.world.get::<Enemy>(ship).unwrap().health, 96);
+    assert!(app.comp_val_at::<Enemy>(ship, 3).is_none());
+}
+
+#[derive(Clone, Debug, Component, PartialEq)]
+struct TurretBlueprint;
+
+/// replicated, but assembly upgrades it
+#[derive(Component, Reflect, Default, Debug, Clone, PartialEq)]
+#[reflect(Component)]
+struct Armor {
+    rating: i32,
+}
+
+fn assemble_turret(entity: &mut EntityWorldMut, _bp: &TurretBlueprint) -> (Armor, Collider) {
+    let rating = entity.get::<Armor>().map_or(0, |armor| armor.rating);
+    (Armor { rating: rating + 1 }, Collider)
+}
+
+#[test]
+fn unassembling_restores_replaced_components() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_type::<Armor>();
+    app.register_blueprint_with_assembler::<TurretBlueprint, _>(assemble_turret);
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health: 10 }).id();
+
+    for _ in 1..=3 {
+        tick(&mut app);
+    }
+
+    let turret = app
+        .world
+        .spawn((
+            Armor { rating: 5 },
+            AssembleBlueprintAtFrame::new(3, TurretBlueprint),
+        ))
+        .id();
+
+    tick(&mut app); // frame 4
+
+    assert_eq!(app.world.get::<Armor>(turret).unwrap().rating, 6);
+    let assembled = app
+        .world
+        .get::<AssembledBlueprint<TurretBlueprint>>(turret)
+        .unwrap();
+    // the armor was already there, so only the collider was added
+    assert_eq!(assembled.components().len(), 1);
+
+    // a rollback to before the assembly frame
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 50 })
+        .unwrap();
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    // unassembling put back the original armor, which was upgraded once again
+    assert_eq!(app.world.get::<Armor>(turret).unwrap().rating, 6);
+    assert!(app.world.get::<Collider>(turret).is_some());
+}
@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+#[derive(Clone, Debug, Component, PartialEq)]
+struct ShipBlueprint;
+
+/// not replicated, only added by blueprint assembly
+#[derive(Component, Debug)]
+struct Collider;
+
+#[derive(Component, Debug, PartialEq)]
+struct AssembledAt(FrameNumber);
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn assemble_ships(
+    q: Query<Entity, Added<ShipBlueprint>>,
+    mut commands: Commands,
+    game_clock: Res<GameClock>,
+) {
+    for entity in q.iter() {
+        info!("Assembling {entity:?} @ {game_clock:?}");
+        commands
+            .entity(entity)
+            .insert((Collider, AssembledAt(game_clock.frame())));
+    }
+}
+
+/// ships only take damage once they have a collider
+fn take_damage(mut q: Query<&mut Enemy, With<Collider>>) {
+    for mut enemy in q.iter_mut() {
+        enemy.health -= 1;
+    }
+}
+
+fn setup() -> App {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_blueprint::<ShipBlueprint>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, assemble_ships, apply_deferred, take_damage)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+    app
+}
+
+#[test]
+fn blueprint_with_spawn_state_assembles_on_exact_frame() {
+    let mut app = setup();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    // the server assembled the ship during frame 3 with 100 health,
+    // and it took 1 damage before replicating.
+    let ship = app
+        .world
+        .spawn((
+            AssembleBlueprintAtFrame::new(3, ShipBlueprint).with_spawn_state(Enemy { health: 100 }),
+            InsertComponentAtFrame::new(3, Enemy { health: 99 }),
+        ))
+        .id();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 3);
+    assert_eq!(app.world.get::<AssembledAt>(ship), Some(&AssembledAt(3)));
+    assert_eq!(app.comp_val_at::<Enemy>(ship, 3).unwrap().health, 99);
+    assert_eq!(app.world.get::<Enemy>(ship).unwrap().health, 96);
+
+    // the server's next snapshot agrees with us
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(ship)
+        .unwrap()
+        .insert(5, Enemy { health: 97 })
+        .unwrap();
+
+    tick(&mut app); // frame 7
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.get::<Enemy>(ship).unwrap().health, 95);
+}
+
+#[test]
+fn blueprint_without_spawn_state_assembles_next_frame() {
+    let mut app = setup();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    let ship = app
+        .world
+        .spawn((
+            AssembleBlueprintAtFrame::new(3, ShipBlueprint),
+            InsertComponentAtFrame::new(3, Enemy { health: 99 }),
+        ))
+        .id();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 4);
+    assert_eq!(app.world.get::<AssembledAt>(ship), Some(&AssembledAt(4)));
+    assert_eq!(app.world.get::<Enemy>(ship).unwrap().health, 96);
+}
@@ -0,0 +1,162 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+#[derive(Clone, Debug, Component, PartialEq)]
+struct MissileBlueprint;
+
+/// not replicated, only added by blueprint assembly
+#[derive(Component, Debug)]
+struct Collider;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<&mut Enemy>) {
+    for mut enemy in q.iter_mut() {
+        enemy.health -= 1;
+    }
+}
+
+/// enemies fire a missile when their health drops to 7, which we predict on the client
+fn fire_missile(q: Query<&Enemy>, mut spawner: KeyedSpawner, game_clock: Res<GameClock>) {
+    for enemy in q.iter() {
+        if enemy.health == 7 {
+            let key = SpawnKey(game_clock.frame() as u64);
+            spawner.spawn(
+                key,
+                AssembleBlueprintAtFrame::new(game_clock.frame(), MissileBlueprint).predicted(),
+            );
+        }
+    }
+}
+
+/// a child entity spawned by assembly
+#[derive(Component, Debug)]
+struct Exhaust;
+
+fn assemble_missiles(q: Query<Entity, Added<MissileBlueprint>>, mut commands: Commands) {
+    for entity in q.iter() {
+        commands
+            .entity(entity)
+            .insert(Collider)
+            .with_children(|parent| {
+                parent.spawn(Exhaust);
+            });
+    }
+}
+
+fn exhausts(app: &mut App) -> Vec<Entity> {
+    app.world
+        .query_filtered::<Entity, With<Exhaust>>()
+        .iter(&app.world)
+        .collect()
+}
+
+fn setup() -> (App, Entity) {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_blueprint::<MissileBlueprint>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, fire_missile, assemble_missiles)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health: 10 }).id();
+    (app, e1)
+}
+
+fn missile(app: &mut App) -> Entity {
+    app.world
+        .query_filtered::<Entity, With<MissileBlueprint>>()
+        .single(&app.world)
+}
+
+fn mispredictions(app: &App) -> Vec<BlueprintMispredicted> {
+    let events = app.world.resource::<Events<BlueprintMispredicted>>();
+    events.get_reader().read(events).copied().collect()
+}
+
+#[test]
+fn mispredicted_blueprint_is_despawned() {
+    let (mut app, e1) = setup();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    // fired during frame 3, assembled for frame 4
+    let missile = missile(&mut app);
+    assert_eq!(
+        app.world.get::<PredictedBlueprint>(missile),
+        Some(&PredictedBlueprint { frame: 4 })
+    );
+    assert!(app.world.get::<Collider>(missile).is_some());
+
+    // the server says the enemy had more health, so it never fired
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 20 })
+        .unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 3);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 16);
+    assert!(app.world.get_entity(missile).is_none());
+    // its children were despawned with it
+    assert!(exhausts(&mut app).is_empty());
+    assert_eq!(
+        mispredictions(&app),
+        vec![BlueprintMispredicted {
+            entity: missile,
+            frame: 4
+        }]
+    );
+}
+
+#[test]
+fn correctly_predicted_blueprint_survives_rollback() {
+    let (mut app, _e1) = setup();
+    let e2 = app.world.spawn(Enemy { health: 100 }).id();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    let missile = missile(&mut app);
+
+    // the server corrects another enemy, but agrees the missile was fired
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e2)
+        .unwrap()
+        .insert(2, Enemy { health: 50 })
+        .unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 3);
+    // the same entity was reassembled during the resimulation
+    assert!(app.world.get::<Collider>(missile).is_some());
+    assert!(app.world.get::<PendingBlueprintAssembly>(missile).is_none());
+    // the child spawned by the first assembly was replaced by the reassembly
+    let exhausts = exhausts(&mut app);
+    assert_eq!(exhausts.len(), 1);
+    assert_eq!(app.world.get::<Parent>(exhausts[0]).unwrap().get(), missile);
+    assert_eq!(
+        app.world.get::<PredictedBlueprint>(missile),
+        Some(&PredictedBlueprint { frame: 4 })
+    );
+    assert!(mispredictions(&app).is_empty());
+}
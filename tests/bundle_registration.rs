@@ -0,0 +1,104 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Shield {
+    strength: i32,
+}
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<&mut Enemy>) {
+    for mut enemy in q.iter_mut() {
+        enemy.health -= 1;
+    }
+}
+
+/// shields recharge by the enemy's remaining health
+fn recharge(mut q: Query<(&Enemy, &mut Shield)>) {
+    for (enemy, mut shield) in q.iter_mut() {
+        shield.strength += enemy.health;
+    }
+}
+
+#[test]
+fn bundle_components_rollback_together() {
+    let mut app = setup_test_app();
+
+    app.register_rollback_bundle::<(Enemy, Shield)>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, recharge)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app
+        .world
+        .spawn((Enemy { health: 10 }, Shield { strength: 0 }))
+        .id();
+
+    for _ in 1..=4 {
+        tick(&mut app);
+    }
+
+    assert_eq!(app.comp_val_at::<Shield>(e1, 2).unwrap().strength, 17);
+    assert_eq!(app.world.get::<Shield>(e1).unwrap().strength, 30);
+
+    // a correction for only one component of the bundle
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 20 })
+        .unwrap();
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    // the shield was restored to frame 2 along with the enemy, and resimulated from there
+    assert_eq!(app.comp_val_at::<Shield>(e1, 2).unwrap().strength, 17);
+    assert_eq!(app.comp_val_at::<Shield>(e1, 3).unwrap().strength, 17 + 19);
+    assert_eq!(
+        app.comp_val_at::<Shield>(e1, 4).unwrap().strength,
+        17 + 19 + 18
+    );
+    assert_eq!(
+        app.world.get::<Shield>(e1).unwrap().strength,
+        17 + 19 + 18 + 17
+    );
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 17);
+}
+
+fn num_systems(app: &App) -> usize {
+    app.world
+        .resource::<Schedules>()
+        .get(FixedUpdate)
+        .unwrap()
+        .systems_len()
+}
+
+#[test]
+fn bundle_components_share_systems() {
+    let mut separate = setup_test_app();
+    separate.register_rollback::<Enemy>();
+    separate.register_rollback::<Shield>();
+
+    let mut bundled = setup_test_app();
+    bundled.register_rollback_bundle::<(Enemy, Shield)>();
+
+    // one system restores both components, and one records both, instead of one each
+    assert_eq!(num_systems(&bundled) + 2, num_systems(&separate));
+
+    // a component already registered on its own keeps its systems, so the bundle isn't shared
+    let mut overlapping = setup_test_app();
+    overlapping.register_rollback::<Enemy>();
+    overlapping.register_rollback_bundle::<(Enemy, Shield)>();
+    assert_eq!(num_systems(&overlapping), num_systems(&separate));
+}
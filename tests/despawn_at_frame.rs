@@ -0,0 +1,94 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<(Entity, &mut Enemy)>) {
+    for (entity, mut enemy) in q.iter_mut() {
+        enemy.health -= 1;
+        info!("{entity:?} took 1 damage -> {enemy:?}");
+    }
+}
+
+/// damages every enemy by the number of other enemies alive
+fn crowd_damage(mut q: Query<&mut Enemy>) {
+    let num = q.iter().count() as i32;
+    for mut enemy in q.iter_mut() {
+        enemy.health -= num - 1;
+    }
+}
+
+#[test]
+fn despawn_at_past_frame() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, crowd_damage)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health: 100 }).id();
+    let e2 = app.world.spawn(Enemy { health: 100 }).id();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    // 1 damage and 1 crowd damage per frame
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 90);
+
+    assert_eq!(
+        app.world.entity_mut(e2).despawn_at_frame(6),
+        Err(TimewarpError::FrameInFuture)
+    );
+    // the server says e2 died during frame 3, so wasn't alive at the end of it
+    app.world.entity_mut(e2).despawn_at_frame(3).unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 3);
+    assert!(app.world.get::<Enemy>(e2).is_none());
+    let ch = app.world.get::<ComponentHistory<Enemy>>(e2).unwrap();
+    assert!(ch.alive_at_frame(2));
+    assert!(!ch.alive_at_frame(3));
+    // e1 took crowd damage on frames 1-3 only
+    assert_eq!(app.comp_val_at::<Enemy>(e1, 3).unwrap().health, 94);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 91);
+
+    // a later rollback doesn't revive e2
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(4, Enemy { health: 50 })
+        .unwrap();
+
+    tick(&mut app); // frame 7
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 2);
+    assert!(app.world.get::<Enemy>(e2).is_none());
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 47);
+
+    // the actual despawn happens rollback_window frames after the despawn frame
+    while app.world.resource::<GameClock>().frame() < 3 + TEST_ROLLBACK_WINDOW - 1 {
+        tick(&mut app);
+    }
+    assert!(app.world.get_entity(e2).is_some());
+    tick(&mut app);
+    assert!(app.world.get_entity(e2).is_none());
+
+    assert_eq!(
+        app.world.entity_mut(e1).despawn_at_frame(1),
+        Err(TimewarpError::FrameTooOld)
+    );
+}
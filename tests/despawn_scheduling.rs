@@ -0,0 +1,42 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+#[test]
+fn despawn_survives_clock_skipping_frames() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+
+    app.add_systems(FixedUpdate, inc_frame.in_set(TimewarpTestSets::GameLogic));
+
+    let e1 = app.world.spawn(Enemy { health: 10 }).id();
+    let e2 = app.world.spawn(Enemy { health: 10 }).id();
+
+    tick(&mut app); // frame 1
+
+    app.world.entity_mut(e1).insert(DespawnMarker::new());
+    app.world.entity_mut(e2).insert(DespawnMarker::new());
+
+    tick(&mut app); // frame 2
+
+    assert_eq!(app.world.resource::<RollbackStats>().pending_despawns, 2);
+
+    // the clock is resynced, jumping over frame 2 + rollback_window
+    app.world
+        .resource_mut::<GameClock>()
+        .set(2 + TEST_ROLLBACK_WINDOW + 3);
+
+    tick(&mut app);
+
+    assert!(app.world.get_entity(e1).is_none());
+    assert!(app.world.get_entity(e2).is_none());
+    assert_eq!(app.world.resource::<RollbackStats>().pending_despawns, 0);
+}
@@ -153,10 +153,12 @@ fn despawn_revival_during_rollback() {
     // so the entity should always exist at the start of frame 4,
     // but should not exist at the start of frame 5.
 
+    // the server's despawn is authoritative, so the rollback below mustn't undo it
     let despawn_frame = 4;
-    app.world
-        .entity_mut(e1)
-        .insert(DespawnMarker::for_frame(despawn_frame));
+    app.world.entity_mut(e1).insert((
+        DespawnMarker::for_frame(despawn_frame),
+        AuthoritativeDespawn,
+    ));
 
     tick(&mut app); // frame 4 - "sometime during frame 4, we despawned e1"
 
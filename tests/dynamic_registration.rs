@@ -0,0 +1,378 @@
+use std::any::TypeId;
+
+use bevy::{ecs::system::CommandQueue, prelude::*};
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+/// a component a mod might add, which timewarp only knows about by reflection
+#[derive(Component, Reflect, Default, Debug, Clone, PartialEq)]
+#[reflect(Component)]
+struct Mana {
+    amount: i32,
+}
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<&mut Enemy>) {
+    for mut enemy in q.iter_mut() {
+        enemy.health -= 1;
+    }
+}
+
+fn regen_mana(mut q: Query<&mut Mana>) {
+    for mut mana in q.iter_mut() {
+        mana.amount += 1;
+    }
+}
+
+fn setup() -> App {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_type::<Mana>();
+    app.register_rollback_by_type_path(Mana::type_path());
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, regen_mana)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+    app
+}
+
+fn mana_at(app: &App, entity: Entity, frame: FrameNumber) -> Option<Mana> {
+    app.world
+        .get::<DynamicComponentHistory>(entity)?
+        .value_at_frame(TypeId::of::<Mana>(), frame)
+        .and_then(Mana::from_reflect)
+}
+
+#[test]
+fn dynamic_component_history_and_snapshots() {
+    let mut app = setup();
+
+    let e1 = app
+        .world
+        .spawn((Enemy { health: 10 }, Mana { amount: 0 }))
+        .id();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    assert_eq!(mana_at(&app, e1, 3), Some(Mana { amount: 3 }));
+    assert_eq!(app.world.get::<Mana>(e1).unwrap().amount, 5);
+
+    // a correct prediction doesn't rollback
+    app.world
+        .get_mut::<DynamicServerSnapshot>(e1)
+        .unwrap()
+        .insert(3, Box::new(Mana { amount: 3 }))
+        .unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 0);
+
+    // the server says we had more mana
+    app.world
+        .get_mut::<DynamicServerSnapshot>(e1)
+        .unwrap()
+        .insert(4, Box::new(Mana { amount: 100 }))
+        .unwrap();
+
+    tick(&mut app); // frame 7
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 5);
+    assert_eq!(mana_at(&app, e1, 4), Some(Mana { amount: 100 }));
+    assert_eq!(mana_at(&app, e1, 5), Some(Mana { amount: 101 }));
+    assert_eq!(app.world.get::<Mana>(e1).unwrap().amount, 103);
+    // statically registered components are rolled back alongside
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 3);
+}
+
+#[test]
+fn dynamic_component_removed_when_rolling_back_before_insertion() {
+    let mut app = setup();
+
+    let e1 = app.world.spawn(Enemy { health: 10 }).id();
+
+    for _ in 1..=3 {
+        tick(&mut app);
+    }
+
+    app.world.entity_mut(e1).insert(Mana { amount: 0 });
+
+    for _ in 4..=5 {
+        tick(&mut app);
+    }
+
+    assert_eq!(mana_at(&app, e1, 3), None);
+    assert_eq!(mana_at(&app, e1, 4), Some(Mana { amount: 1 }));
+
+    // rollback to before the mana was added
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 50 })
+        .unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert!(app.world.get::<Mana>(e1).is_none());
+    assert_eq!(mana_at(&app, e1, 5), None);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 46);
+}
+
+#[test]
+fn unregistered_types_are_ignored() {
+    let mut app = setup_test_app();
+    // not in the type registry, so this only logs an error
+    app.register_rollback_by_type_path("not_a_mod::Nothing");
+    app.register_rollback_by_type_id(TypeId::of::<Mana>());
+    tick(&mut app);
+}
+
+#[test]
+fn register_from_world_after_startup() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_type::<Mana>();
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, regen_mana)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app
+        .world
+        .spawn((Enemy { health: 10 }, Mana { amount: 0 }))
+        .id();
+
+    for _ in 1..=2 {
+        tick(&mut app);
+    }
+
+    // eg a mod loaded once the game is running
+    app.world.register_rollback_by_type_path(Mana::type_path());
+
+    for _ in 3..=5 {
+        tick(&mut app);
+    }
+
+    assert_eq!(mana_at(&app, e1, 2), None);
+    assert_eq!(mana_at(&app, e1, 3), Some(Mana { amount: 3 }));
+
+    app.world
+        .get_mut::<DynamicServerSnapshot>(e1)
+        .unwrap()
+        .insert(4, Box::new(Mana { amount: 100 }))
+        .unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(mana_at(&app, e1, 5), Some(Mana { amount: 101 }));
+    assert_eq!(app.world.get::<Mana>(e1).unwrap().amount, 102);
+}
+
+#[test]
+fn dynamic_components_despawned_at_frame() {
+    let mut app = setup();
+
+    let e1 = app
+        .world
+        .spawn((Enemy { health: 10 }, Mana { amount: 0 }))
+        .id();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    app.world.entity_mut(e1).despawn_at_frame(4).unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert!(app.world.get::<Mana>(e1).is_none());
+    assert_eq!(mana_at(&app, e1, 3), Some(Mana { amount: 3 }));
+    assert_eq!(mana_at(&app, e1, 4), None);
+    assert_eq!(mana_at(&app, e1, 5), None);
+
+    // resimulating the frames before the despawn keeps the mana until the despawn frame
+    app.world
+        .get_mut::<DynamicServerSnapshot>(e1)
+        .unwrap()
+        .insert(2, Box::new(Mana { amount: 100 }))
+        .unwrap();
+
+    tick(&mut app); // frame 7
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 2);
+    assert_eq!(mana_at(&app, e1, 3), Some(Mana { amount: 101 }));
+    assert_eq!(mana_at(&app, e1, 4), None);
+    assert!(app.world.get::<Mana>(e1).is_none());
+}
+
+#[test]
+fn dynamic_components_revived_when_despawn_undone() {
+    let mut app = setup();
+
+    let e1 = app
+        .world
+        .spawn((Enemy { health: 10 }, Mana { amount: 0 }))
+        .id();
+
+    for _ in 1..=4 {
+        tick(&mut app);
+    }
+
+    // predicted by our game logic
+    app.world.entity_mut(e1).insert(DespawnMarker::new());
+
+    tick(&mut app); // frame 5
+
+    assert!(app.world.get::<Mana>(e1).is_none());
+    assert_eq!(mana_at(&app, e1, 5), None);
+
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(3, Enemy { health: 50 })
+        .unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert!(app.world.get::<DespawnMarker>(e1).is_none());
+    assert_eq!(mana_at(&app, e1, 5), Some(Mana { amount: 5 }));
+    assert_eq!(app.world.get::<Mana>(e1).unwrap().amount, 6);
+}
+
+#[test]
+fn dynamic_components_spawned_at_frame() {
+    let mut app = setup();
+
+    for _ in 1..=4 {
+        tick(&mut app);
+    }
+
+    let mut queue = CommandQueue::default();
+    let e1 = Commands::new(&mut queue, &app.world)
+        .spawn_at_frame(2, (Enemy { health: 100 }, Mana { amount: 0 }))
+        .id();
+    queue.apply(&mut app.world);
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(mana_at(&app, e1, 2), Some(Mana { amount: 0 }));
+    assert_eq!(mana_at(&app, e1, 4), Some(Mana { amount: 2 }));
+    assert_eq!(app.world.get::<Mana>(e1).unwrap().amount, 3);
+}
+
+#[test]
+fn server_entity_adopts_dynamic_components() {
+    let mut app = setup();
+
+    tick(&mut app); // frame 1
+
+    let predicted = app
+        .world
+        .spawn((Mana { amount: 0 }, PredictedSpawn::new(SpawnKey(1))))
+        .id();
+
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+
+    let server_entity = app.world.spawn(ServerSpawnKey(SpawnKey(1))).id();
+
+    tick(&mut app); // frame 4
+
+    assert!(app.world.get_entity(predicted).is_none());
+    assert_eq!(app.world.get::<Mana>(server_entity).unwrap().amount, 3);
+    assert_eq!(mana_at(&app, server_entity, 2), Some(Mana { amount: 1 }));
+
+    // server corrections to the adopted history cause a rollback
+    app.world
+        .get_mut::<DynamicServerSnapshot>(server_entity)
+        .unwrap()
+        .insert(3, Box::new(Mana { amount: 10 }))
+        .unwrap();
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.get::<Mana>(server_entity).unwrap().amount, 12);
+}
+
+/// enemies spawn a mana orb whenever their health is even
+fn drop_mana(q: Query<&Enemy>, mut spawner: KeyedSpawner, game_clock: Res<GameClock>) {
+    for enemy in q.iter() {
+        if enemy.health % 2 == 0 {
+            spawner.spawn(
+                SpawnKey(game_clock.frame() as u64),
+                Mana {
+                    amount: enemy.health,
+                },
+            );
+        }
+    }
+}
+
+#[test]
+fn keyed_spawns_unspawn_dynamic_components() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_type::<Mana>();
+    app.register_rollback_by_type_path(Mana::type_path());
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, drop_mana)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health: 10 }).id();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+    // dropped on frames 2 and 4
+    let orb = app
+        .world
+        .resource::<KeyedSpawns>()
+        .entity(SpawnKey(4))
+        .unwrap();
+    assert_eq!(mana_at(&app, orb, 4), Some(Mana { amount: 6 }));
+
+    // the server says e1 had more health, so it still drops an orb on frame 4
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 100 })
+        .unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(
+        app.world.resource::<KeyedSpawns>().entity(SpawnKey(4)),
+        Some(orb)
+    );
+    // the predicted history was forgotten, and recorded again from the resimulated spawn
+    assert_eq!(mana_at(&app, orb, 3), None);
+    assert_eq!(mana_at(&app, orb, 4), Some(Mana { amount: 98 }));
+    assert_eq!(app.world.get::<Mana>(orb).unwrap().amount, 98);
+}
@@ -0,0 +1,176 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+#[derive(Resource)]
+struct Ships {
+    ship1: Entity,
+    ship2: Entity,
+}
+
+#[derive(Component)]
+struct Turret;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<(Entity, &mut Enemy), Without<Turret>>) {
+    for (entity, mut enemy) in q.iter_mut() {
+        enemy.health -= 1;
+        info!("{entity:?} took 1 damage -> {enemy:?}");
+    }
+}
+
+/// turrets jump ship once ship1 is badly damaged
+fn abandon_ship(
+    ships: Res<Ships>,
+    q_ships: Query<&Enemy, Without<Turret>>,
+    q_turrets: Query<(Entity, &Parent), With<Turret>>,
+    mut commands: Commands,
+) {
+    let Ok(ship1) = q_ships.get(ships.ship1) else {
+        return;
+    };
+    if ship1.health > 7 {
+        return;
+    }
+    for (turret, parent) in q_turrets.iter() {
+        if parent.get() == ships.ship1 {
+            info!("{turret:?} abandoning ship");
+            commands.entity(turret).set_parent(ships.ship2);
+        }
+    }
+}
+
+fn despawn_dead(
+    q: Query<(Entity, &Enemy), (Without<DespawnMarker>, Without<Turret>)>,
+    mut commands: Commands,
+) {
+    for (entity, enemy) in q.iter() {
+        if enemy.health <= 0 {
+            commands.entity(entity).insert(DespawnMarker::new());
+        }
+    }
+}
+
+#[test]
+fn rollback_restores_parent() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_rollback_hierarchy();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, abandon_ship)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let ship1 = app.world.spawn(Enemy { health: 10 }).id();
+    let ship2 = app.world.spawn(Enemy { health: 10 }).id();
+    let turret = app
+        .world
+        .spawn((Enemy { health: 1 }, Turret))
+        .set_parent(ship1)
+        .id();
+    app.insert_resource(Ships { ship1, ship2 });
+
+    tick(&mut app); // frame 1
+    tick(&mut app); // frame 2
+    assert_eq!(app.world.get::<Parent>(turret).unwrap().get(), ship1);
+    assert!(app.world.get::<ParentHistory>(turret).is_some());
+
+    tick(&mut app); // frame 3
+    tick(&mut app); // frame 4
+
+    // ship1 took too much damage on frame 3
+    assert_eq!(app.world.get::<Parent>(turret).unwrap().get(), ship2);
+    let ph = app.world.get::<ParentHistory>(turret).unwrap();
+    assert_eq!(ph.parent_at_frame(2), Some(Some(ship1)));
+    assert_eq!(ph.parent_at_frame(3), Some(Some(ship2)));
+
+    // but the server says ship1 was fine
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(ship1)
+        .unwrap()
+        .insert(2, Enemy { health: 100 })
+        .unwrap();
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.get::<Parent>(turret).unwrap().get(), ship1);
+    assert!(app.world.get::<Children>(ship2).is_none());
+    let ph = app.world.get::<ParentHistory>(turret).unwrap();
+    assert_eq!(ph.parent_at_frame(3), Some(Some(ship1)));
+    assert_eq!(ph.parent_at_frame(5), Some(Some(ship1)));
+}
+
+#[test]
+fn children_despawn_and_revive_with_parent() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, despawn_dead)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let ship = app.world.spawn(Enemy { health: 3 }).id();
+    let turret = app
+        .world
+        .spawn((Enemy { health: 1 }, Turret))
+        .set_parent(ship)
+        .id();
+
+    tick(&mut app); // frame 1
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+
+    // the ship was predicted to die on frame 3, taking the turret with it
+    assert!(app.world.get::<Enemy>(ship).is_none());
+    assert!(app.world.get::<Enemy>(turret).is_none());
+    assert!(app.world.get::<DespawnMarker>(turret).is_some());
+    assert!(app.world.get::<AuthoritativeDespawn>(turret).is_none());
+
+    tick(&mut app); // frame 4
+
+    // but the server says it had more health
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(ship)
+        .unwrap()
+        .insert(2, Enemy { health: 100 })
+        .unwrap();
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.get::<Enemy>(ship).unwrap().health, 97);
+    assert_eq!(app.world.get::<Enemy>(turret).unwrap().health, 1);
+    assert!(app.world.get::<DespawnMarker>(turret).is_none());
+
+    // the server despawns the ship for real
+    app.world.entity_mut(ship).despawn_at_frame(4).unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert!(app.world.get::<Enemy>(ship).is_none());
+    assert!(app.world.get::<Enemy>(turret).is_none());
+    let ch = app.world.get::<ComponentHistory<Enemy>>(turret).unwrap();
+    assert!(ch.alive_at_frame(3));
+    assert!(!ch.alive_at_frame(4));
+
+    while app.world.resource::<GameClock>().frame() < 4 + TEST_ROLLBACK_WINDOW {
+        tick(&mut app);
+    }
+    assert!(app.world.get_entity(ship).is_none());
+    assert!(app.world.get_entity(turret).is_none());
+}
@@ -0,0 +1,88 @@
+use bevy::{ecs::system::SystemState, prelude::*};
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<(Entity, &mut Enemy, &EntName)>) {
+    for (entity, mut enemy, name) in q.iter_mut() {
+        enemy.health -= 1;
+        info!("{entity:?} took 1 damage -> {enemy:?} {name:?}");
+    }
+}
+
+#[test]
+fn historical_query() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app
+        .world
+        .spawn((
+            Enemy { health: 10 },
+            EntName {
+                name: "E1".to_owned(),
+            },
+        ))
+        .id();
+    let e2 = app
+        .world
+        .spawn((
+            Enemy { health: 100 },
+            EntName {
+                name: "E2".to_owned(),
+            },
+        ))
+        .id();
+
+    tick(&mut app); // frame 1
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+
+    // e2 dies during frame 4
+    app.world.entity_mut(e2).insert(DespawnMarker::new());
+
+    tick(&mut app); // frame 4
+    tick(&mut app); // frame 5
+
+    let mut state: SystemState<HistoricalQuery<Enemy>> = SystemState::new(&mut app.world);
+    let hq = state.get(&app.world);
+
+    assert_eq!(hq.get_at(e1, 2).unwrap().unwrap().health, 8);
+    assert_eq!(hq.get_at(e1, 5).unwrap().unwrap().health, 5);
+    assert_eq!(hq.get_at(e2, 3).unwrap().unwrap().health, 97);
+    // a value for the death frame is buffered, but the component isn't alive then:
+    assert!(app.comp_val_at::<Enemy>(e2, 4).is_some());
+    assert!(hq.get_at(e2, 4).unwrap().is_none());
+    assert!(!hq.contains_at(e2, 5));
+    // frames we never simulated:
+    assert!(hq.get_at(e1, 6).unwrap().is_none());
+
+    let at_frame_3 = hq.iter_at(3).collect::<Vec<_>>();
+    assert_eq!(at_frame_3.len(), 2);
+    let at_frame_5 = hq
+        .iter_at(5)
+        .filter(|(_, v)| v.is_some())
+        .collect::<Vec<_>>();
+    assert_eq!(at_frame_5, vec![(e1, Some(&Enemy { health: 5 }))]);
+
+    // filters work as they would for a normal query:
+    let mut state: SystemState<HistoricalQuery<Enemy, Without<DespawnMarker>>> =
+        SystemState::new(&mut app.world);
+    let hq = state.get(&app.world);
+    assert_eq!(hq.iter_at(3).count(), 1);
+    assert!(hq.get_at(e2, 3).is_err());
+}
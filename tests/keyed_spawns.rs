@@ -0,0 +1,208 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Bullet {
+    power: i32,
+}
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<&mut Enemy>) {
+    for mut enemy in q.iter_mut() {
+        enemy.health -= 1;
+    }
+}
+
+/// enemies fire a bullet whenever their health is even
+fn shoot(q: Query<&Enemy>, mut spawner: KeyedSpawner, game_clock: Res<GameClock>) {
+    for enemy in q.iter() {
+        if enemy.health % 2 == 0 {
+            let key = SpawnKey(game_clock.frame() as u64);
+            let bullet = spawner
+                .spawn(
+                    key,
+                    Bullet {
+                        power: enemy.health,
+                    },
+                )
+                .id();
+            info!("{bullet:?} fired with {key:?}");
+        }
+    }
+}
+
+fn setup(health: i32) -> (App, Entity) {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_rollback::<Bullet>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, shoot)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health }).id();
+    (app, e1)
+}
+
+fn bullets(app: &mut App) -> Vec<(Entity, SpawnKey, Bullet)> {
+    let mut bullets = app
+        .world
+        .query::<(Entity, &SpawnKey, &Bullet)>()
+        .iter(&app.world)
+        .map(|(e, k, b)| (e, *k, b.clone()))
+        .collect::<Vec<_>>();
+    bullets.sort_by_key(|(_, k, _)| k.0);
+    bullets
+}
+
+#[test]
+fn resimulated_spawns_reuse_entity() {
+    let (mut app, e1) = setup(10);
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+    // shot on frames 2 and 4
+    let before = bullets(&mut app);
+    assert_eq!(before.len(), 2);
+    assert_eq!(before[1].1, SpawnKey(4));
+    assert_eq!(before[1].2.power, 6);
+
+    // the server says e1 had more health, so it still shoots on frame 4
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 100 })
+        .unwrap();
+
+    tick(&mut app); // frame 6, shoots again
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    let after = bullets(&mut app);
+    assert_eq!(after.len(), 3);
+    assert_eq!(after[0], before[0]);
+    // same entity, resimulated value
+    assert_eq!(after[1].0, before[1].0);
+    assert_eq!(after[1].2.power, 98);
+    assert_eq!(app.comp_val_at::<Bullet>(after[1].0, 4).unwrap().power, 98);
+    assert_eq!(
+        app.world.resource::<KeyedSpawns>().entity(SpawnKey(4)),
+        Some(before[1].0)
+    );
+}
+
+#[test]
+fn spawns_missing_from_resimulation_are_despawned() {
+    let (mut app, e1) = setup(10);
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+    let before = bullets(&mut app);
+    assert_eq!(before.len(), 2);
+
+    // the server says e1 had odd health at frame 2, so it shoots on frame 3 and 5 instead
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 99 })
+        .unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    let after = bullets(&mut app);
+    assert_eq!(
+        after.iter().map(|(_, k, _)| k.0).collect::<Vec<_>>(),
+        vec![2, 3, 5]
+    );
+    assert_eq!(after[0], before[0]);
+    assert!(app.world.get_entity(before[1].0).is_none());
+    assert_eq!(
+        app.world.resource::<KeyedSpawns>().entity(SpawnKey(4)),
+        None
+    );
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Tracer;
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Homing;
+
+/// like `shoot`, but bullets have a tracer child
+fn shoot_with_tracer(q: Query<&Enemy>, mut spawner: KeyedSpawner, game_clock: Res<GameClock>) {
+    for enemy in q.iter() {
+        if enemy.health % 2 == 0 {
+            let key = SpawnKey(game_clock.frame() as u64);
+            spawner
+                .spawn(
+                    key,
+                    Bullet {
+                        power: enemy.health,
+                    },
+                )
+                .with_children(|parent| {
+                    parent.spawn(Tracer);
+                });
+        }
+    }
+}
+
+#[test]
+fn unspawning_keeps_history_and_despawns_children() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_rollback::<Bullet>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, shoot_with_tracer)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health: 10 }).id();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+    let before = bullets(&mut app);
+    assert_eq!(before.len(), 2);
+    let bullet = before[1].0;
+    // added by something other than the spawner
+    app.world.entity_mut(bullet).insert(Homing);
+
+    // the server says e1 had more health, so it still shoots on frame 4
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 100 })
+        .unwrap();
+
+    tick(&mut app); // frame 6, shoots again
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    let after = bullets(&mut app);
+    assert_eq!(after.len(), 3);
+    assert_eq!(after[1].0, bullet);
+    assert_eq!(after[1].2.power, 98);
+    // components timewarp doesn't know about are left alone
+    assert!(app.world.get::<Homing>(bullet).is_some());
+    assert!(app.world.get::<TimewarpStatus>(bullet).is_some());
+    // the old tracer was despawned, rather than orphaned, and the resimulation spawned a new one
+    assert_eq!(app.world.query::<&Tracer>().iter(&app.world).count(), 3);
+    assert_eq!(app.world.get::<Children>(bullet).unwrap().len(), 1);
+}
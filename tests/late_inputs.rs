@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+/// each frame, players take damage according to their input for that frame.
+fn apply_inputs(
+    game_clock: Res<GameClock>,
+    mut q: Query<(Entity, &mut Enemy, &InputBuffer<Damage>)>,
+) {
+    for (entity, mut enemy, inputs) in q.iter_mut() {
+        if let Some(damage) = inputs.input_at(game_clock.frame()) {
+            enemy.health -= damage.0;
+            info!("{entity:?} took {damage:?} -> {enemy:?}");
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Damage(i32);
+
+#[test]
+fn server_resimulates_late_inputs() {
+    let mut app = setup_test_app_with_config(
+        test_config()
+            .with_mode(TimewarpMode::Server)
+            .with_late_input_window(3)
+            .with_consolidation_strategy(RollbackConsolidationStrategy::Oldest),
+    );
+
+    app.register_rollback::<Enemy>();
+    app.register_input::<Damage>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, apply_inputs)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app
+        .world
+        .spawn((
+            Enemy { health: 100 },
+            InputBuffer::<Damage>::with_capacity(TEST_ROLLBACK_WINDOW as usize),
+        ))
+        .id();
+    let e2 = app
+        .world
+        .spawn((
+            Enemy { health: 100 },
+            InputBuffer::<Damage>::with_capacity(TEST_ROLLBACK_WINDOW as usize),
+        ))
+        .id();
+
+    for f in 1..=5 {
+        // e1's inputs always arrive on time
+        let mut inputs = app.world.get_mut::<InputBuffer<Damage>>(e1).unwrap();
+        inputs.insert(f, Damage(1)).unwrap();
+        tick(&mut app);
+    }
+
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 95);
+    assert_eq!(app.world.get::<Enemy>(e2).unwrap().health, 100);
+    assert!(app.world.get::<ServerSnapshot<Enemy>>(e1).is_none());
+
+    // e2's input for frame 4 arrives 2 frames late:
+    let mut inputs = app.world.get_mut::<InputBuffer<Damage>>(e2).unwrap();
+    inputs.insert(4, Damage(10)).unwrap();
+    let mut inputs = app.world.get_mut::<InputBuffer<Damage>>(e1).unwrap();
+    inputs.insert(6, Damage(1)).unwrap();
+
+    tick(&mut app); // frame 6, with rollback
+
+    assert_eq!(
+        app.world
+            .get_resource::<RollbackStats>()
+            .unwrap()
+            .num_rollbacks,
+        1
+    );
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 4);
+    assert_eq!(app.comp_val_at::<Enemy>(e2, 3).unwrap().health, 100);
+    assert_eq!(app.comp_val_at::<Enemy>(e2, 4).unwrap().health, 90);
+    assert_eq!(app.world.get::<Enemy>(e2).unwrap().health, 90);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 94);
+
+    // the frames that changed are marked, so they can be rebroadcast:
+    let correction = app.world.get::<ServerCorrection<Enemy>>(e2).unwrap();
+    assert_eq!(correction.frames, vec![4, 5]);
+    assert!(app.world.get::<ServerCorrection<Enemy>>(e1).is_none());
+
+    // a revised input for e1, but too late, gets ignored:
+    let mut inputs = app.world.get_mut::<InputBuffer<Damage>>(e1).unwrap();
+    inputs.insert(2, Damage(10)).unwrap();
+    inputs.insert(7, Damage(1)).unwrap();
+
+    tick(&mut app); // frame 7
+
+    let stats = app.world.get_resource::<RollbackStats>().unwrap();
+    assert_eq!(stats.num_rollbacks, 1);
+    assert_eq!(stats.late_inputs_rejected, 1);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 93);
+}
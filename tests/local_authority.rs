@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<(Entity, &mut Enemy)>) {
+    for (entity, mut enemy) in q.iter_mut() {
+        enemy.health -= 1;
+        info!("{entity:?} took 1 damage -> {enemy:?}");
+    }
+}
+
+fn turn(mut q: Query<&mut Aim>) {
+    for mut aim in q.iter_mut() {
+        aim.0 += 1;
+    }
+}
+
+/// owned by the client, not the server
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Aim(u32);
+
+#[test]
+fn locally_owned_components_ignore_snapshots() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_rollback::<Aim>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, turn)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app
+        .world
+        .spawn((Enemy { health: 100 }, Aim(0), LocalAuthority::<Aim>::new()))
+        .id();
+
+    tick(&mut app); // frame 1
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+    tick(&mut app); // frame 4
+
+    // the server echoes back an outdated aim:
+    app.world
+        .get_mut::<ServerSnapshot<Aim>>(e1)
+        .unwrap()
+        .insert(2, Aim(99))
+        .unwrap();
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 0);
+    assert_eq!(app.world.get::<Aim>(e1).unwrap().0, 5);
+    assert_eq!(app.comp_val_at::<Aim>(e1, 2).unwrap().0, 2);
+    // it was recorded though:
+    let ss = app.world.get::<ServerSnapshot<Aim>>(e1).unwrap();
+    assert_eq!(ss.at_frame(2).unwrap().0, 99);
+
+    // the server also corrects our health, which it does own, causing a rollback to frame 4:
+    app.world
+        .get_mut::<ServerSnapshot<Aim>>(e1)
+        .unwrap()
+        .insert(3, Aim(99))
+        .unwrap();
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(3, Enemy { health: 50 })
+        .unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 47);
+    // aim was restored from our own history, not the echoed snapshot
+    assert_eq!(app.world.get::<Aim>(e1).unwrap().0, 6);
+
+    // hand aim back to the server from frame 7:
+    app.world
+        .get_mut::<LocalAuthority<Aim>>(e1)
+        .unwrap()
+        .release_at(7);
+
+    tick(&mut app); // frame 7
+    tick(&mut app); // frame 8
+
+    app.world
+        .get_mut::<ServerSnapshot<Aim>>(e1)
+        .unwrap()
+        .insert(7, Aim(70))
+        .unwrap();
+
+    tick(&mut app); // frame 9
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 2);
+    assert_eq!(app.world.get::<Aim>(e1).unwrap().0, 72);
+}
@@ -0,0 +1,113 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn apply_inputs(
+    game_clock: Res<GameClock>,
+    mut q: Query<(Entity, &mut Enemy, &InputBuffer<Damage>)>,
+) {
+    for (entity, mut enemy, inputs) in q.iter_mut() {
+        let damage = inputs
+            .input_at(game_clock.frame())
+            .expect("lockstep waits for all inputs");
+        enemy.health -= damage.0;
+        info!("{entity:?} took {damage:?} -> {enemy:?}");
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Damage(i32);
+
+fn insert_input(app: &mut App, entity: Entity, frame: FrameNumber, damage: i32) {
+    app.world
+        .get_mut::<InputBuffer<Damage>>(entity)
+        .unwrap()
+        .insert(frame, Damage(damage))
+        .unwrap();
+}
+
+#[test]
+fn lockstep_stalls_until_inputs_arrive() {
+    let mut app = setup_test_app_with_mode(TimewarpMode::Lockstep);
+
+    app.register_rollback::<Enemy>();
+    app.register_input::<Damage>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, apply_inputs)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app
+        .world
+        .spawn((
+            Enemy { health: 100 },
+            InputBuffer::<Damage>::with_capacity(TEST_ROLLBACK_WINDOW as usize),
+        ))
+        .id();
+    let e2 = app
+        .world
+        .spawn((
+            Enemy { health: 100 },
+            InputBuffer::<Damage>::with_capacity(TEST_ROLLBACK_WINDOW as usize),
+        ))
+        .id();
+
+    insert_input(&mut app, e1, 1, 1);
+    insert_input(&mut app, e2, 1, 2);
+    tick(&mut app); // frame 1
+
+    assert_eq!(app.world.resource::<GameClock>().frame(), 1);
+    assert_eq!(app.world.get::<Enemy>(e2).unwrap().health, 98);
+
+    // e2's input for frame 2 hasn't arrived:
+    insert_input(&mut app, e1, 2, 1);
+    tick(&mut app);
+    tick(&mut app);
+
+    assert_eq!(app.world.resource::<GameClock>().frame(), 1);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 99);
+    let stats = app.world.resource::<LockstepStats>();
+    assert!(!stats.is_ready());
+    assert_eq!(stats.stalled_ticks, 2);
+    assert_eq!(stats.current_stall, 2);
+
+    insert_input(&mut app, e2, 2, 2);
+    tick(&mut app); // frame 2
+
+    assert_eq!(app.world.resource::<GameClock>().frame(), 2);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 98);
+    assert_eq!(app.world.get::<Enemy>(e2).unwrap().health, 96);
+    let stats = app.world.resource::<LockstepStats>();
+    assert!(stats.is_ready());
+    assert_eq!(stats.current_stall, 0);
+    assert_eq!(stats.longest_stall, 2);
+
+    // inputs can arrive early, and we never predict or rollback:
+    for f in 3..=5 {
+        insert_input(&mut app, e1, f, 1);
+        insert_input(&mut app, e2, f, 2);
+    }
+    tick(&mut app); // frame 3
+    tick(&mut app); // frame 4
+    tick(&mut app); // frame 5
+    tick(&mut app); // stalled
+
+    assert_eq!(app.world.resource::<GameClock>().frame(), 5);
+    assert_eq!(app.world.resource::<ConfirmedFrame>().frame(), Some(5));
+    assert_eq!(app.world.get::<Enemy>(e2).unwrap().health, 90);
+    assert_eq!(app.world.resource::<LockstepStats>().stalled_ticks, 3);
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 0);
+    // history is recorded as usual
+    assert_eq!(app.comp_val_at::<Enemy>(e2, 2).unwrap().health, 96);
+    assert!(app.world.get::<ServerSnapshot<Enemy>>(e2).is_none());
+}
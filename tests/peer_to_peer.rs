@@ -0,0 +1,191 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+/// each frame, players take damage according to their (possibly predicted) input.
+fn apply_inputs(
+    game_clock: Res<GameClock>,
+    mut q: Query<(Entity, &mut Enemy, &InputBuffer<Damage>)>,
+) {
+    for (entity, mut enemy, inputs) in q.iter_mut() {
+        if let Some(damage) = inputs.predicted_input_at(game_clock.frame()) {
+            enemy.health -= damage.0;
+            info!("{entity:?} took {damage:?} -> {enemy:?}");
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Damage(i32);
+
+fn insert_input(app: &mut App, entity: Entity, frame: FrameNumber, damage: i32) {
+    app.world
+        .get_mut::<InputBuffer<Damage>>(entity)
+        .unwrap()
+        .insert(frame, Damage(damage))
+        .unwrap();
+}
+
+fn confirmed_frame(app: &App) -> Option<FrameNumber> {
+    app.world.resource::<ConfirmedFrame>().frame()
+}
+
+fn setup_peer_to_peer() -> App {
+    setup_test_app_with_config(
+        test_config()
+            .with_mode(TimewarpMode::PeerToPeer)
+            .with_consolidation_strategy(RollbackConsolidationStrategy::Oldest),
+    )
+}
+
+#[test]
+fn peer_to_peer_rollback_on_misprediction() {
+    let mut app = setup_peer_to_peer();
+
+    app.register_rollback::<Enemy>();
+    app.register_input::<Damage>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, apply_inputs)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let local = app
+        .world
+        .spawn((
+            Enemy { health: 100 },
+            InputBuffer::<Damage>::with_capacity(TEST_ROLLBACK_WINDOW as usize),
+        ))
+        .id();
+    let remote = app
+        .world
+        .spawn((
+            Enemy { health: 100 },
+            InputBuffer::<Damage>::with_capacity(TEST_ROLLBACK_WINDOW as usize),
+        ))
+        .id();
+    // not controlled by anyone
+    let e3 = app.world.spawn(Enemy { health: 100 }).id();
+
+    for f in 1..=5 {
+        insert_input(&mut app, local, f, 1);
+        // remote inputs stop arriving after frame 2
+        if f <= 2 {
+            insert_input(&mut app, remote, f, 1);
+        }
+        tick(&mut app);
+    }
+
+    // remote inputs for 3,4,5 were predicted to be the same as frame 2
+    assert_eq!(app.world.get::<Enemy>(remote).unwrap().health, 95);
+    assert_eq!(confirmed_frame(&app), Some(2));
+    assert!(app.world.get::<ServerSnapshot<Enemy>>(remote).is_none());
+
+    // remote input for 3 arrives, and we predicted it correctly:
+    insert_input(&mut app, remote, 3, 1);
+    insert_input(&mut app, local, 6, 1);
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 0);
+    assert_eq!(confirmed_frame(&app), Some(3));
+    assert_eq!(app.world.get::<Enemy>(remote).unwrap().health, 94);
+
+    // remote input for 4 arrives, and differs from our prediction:
+    insert_input(&mut app, remote, 4, 5);
+    insert_input(&mut app, local, 7, 1);
+    tick(&mut app); // frame 7
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 4);
+    assert_eq!(confirmed_frame(&app), Some(4));
+    // 3 frames of 1 damage, then 4 frames of 5 damage (4 actual, 5,6,7 predicted)
+    assert_eq!(app.comp_val_at::<Enemy>(remote, 4).unwrap().health, 92);
+    assert_eq!(app.world.get::<Enemy>(remote).unwrap().health, 77);
+    assert_eq!(app.world.get::<Enemy>(local).unwrap().health, 93);
+
+    // e3 dies during frame 8
+    app.world.entity_mut(e3).insert(DespawnMarker::new());
+    insert_input(&mut app, local, 8, 1);
+    tick(&mut app); // frame 8
+
+    assert!(app.world.get_entity(e3).is_some());
+    assert!(app.world.get::<Enemy>(e3).is_none());
+
+    // remaining remote inputs arrive, matching our predictions:
+    for f in 5..=8 {
+        insert_input(&mut app, remote, f, 5);
+    }
+    insert_input(&mut app, local, 9, 1);
+    tick(&mut app); // frame 9
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(confirmed_frame(&app), Some(8));
+    // frame 8 is confirmed, so e3 can never be revived, and gets despawned
+    // without waiting for the rollback window to elapse.
+    assert!(app.world.get_entity(e3).is_none());
+}
+
+/// there are no snapshots, but InsertComponentAtFrame still works
+#[test]
+fn peer_to_peer_inserts_component_at_frame() {
+    let mut app = setup_peer_to_peer();
+
+    app.register_rollback::<Enemy>();
+
+    app.add_systems(FixedUpdate, inc_frame.in_set(TimewarpTestSets::GameLogic));
+
+    let e1 = app.world.spawn(Enemy { health: 100 }).id();
+    let e2 = app.world.spawn_empty().id();
+
+    for _ in 1..=4 {
+        tick(&mut app);
+    }
+
+    app.world
+        .entity_mut(e1)
+        .insert(InsertComponentAtFrame::new(2, Enemy { health: 50 }));
+    app.world
+        .entity_mut(e2)
+        .insert(InsertComponentAtFrame::new(3, Enemy { health: 20 }));
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 3);
+    assert_eq!(app.comp_val_at::<Enemy>(e1, 2).unwrap().health, 50);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 50);
+    assert_eq!(app.world.get::<Enemy>(e2).unwrap().health, 20);
+    assert!(app.world.get::<ServerSnapshot<Enemy>>(e1).is_none());
+    assert!(app.world.get::<ServerSnapshot<Enemy>>(e2).is_none());
+}
+
+/// the confirmed frame counts from the start frame, and never skips a missing input
+#[test]
+fn confirmed_frame_waits_for_missing_inputs() {
+    let mut inputs = InputBuffer::<Damage>::with_capacity(TEST_ROLLBACK_WINDOW as usize);
+    inputs.insert(2, Damage(1)).unwrap();
+    assert_eq!(inputs.confirmed_frame(), None);
+    inputs.insert(1, Damage(1)).unwrap();
+    assert_eq!(inputs.confirmed_frame(), Some(2));
+    inputs.insert(4, Damage(1)).unwrap();
+    assert_eq!(inputs.confirmed_frame(), Some(2));
+    inputs.insert(3, Damage(1)).unwrap();
+    assert_eq!(inputs.confirmed_frame(), Some(4));
+
+    // a player who joined at frame 50
+    let mut inputs =
+        InputBuffer::<Damage>::with_capacity_starting_at(TEST_ROLLBACK_WINDOW as usize, 50);
+    inputs.insert(51, Damage(1)).unwrap();
+    assert_eq!(inputs.confirmed_frame(), None);
+    inputs.insert(50, Damage(1)).unwrap();
+    assert_eq!(inputs.confirmed_frame(), Some(51));
+}
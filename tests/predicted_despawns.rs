@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<(Entity, &mut Enemy)>) {
+    for (entity, mut enemy) in q.iter_mut() {
+        enemy.health -= 1;
+        info!("{entity:?} took 1 damage -> {enemy:?}");
+    }
+}
+
+/// our game logic predicts that enemies with no health left die
+fn despawn_dead(q: Query<(Entity, &Enemy), Without<DespawnMarker>>, mut commands: Commands) {
+    for (entity, enemy) in q.iter() {
+        if enemy.health <= 0 {
+            info!("{entity:?} died -> {enemy:?}");
+            commands.entity(entity).insert(DespawnMarker::new());
+        }
+    }
+}
+
+#[test]
+fn predicted_despawn_undone_by_rollback() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, despawn_dead)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health: 3 }).id();
+
+    tick(&mut app); // frame 1
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+
+    // we predicted e1 dies on frame 3
+    assert!(app.world.get::<Enemy>(e1).is_none());
+    assert_eq!(
+        app.world.get::<DespawnMarker>(e1),
+        Some(&DespawnMarker(Some(3)))
+    );
+    assert!(app.world.get::<AuthoritativeDespawn>(e1).is_none());
+
+    tick(&mut app); // frame 4
+
+    // but the server says e1 had more health than we thought
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 100 })
+        .unwrap();
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert!(app.world.get::<DespawnMarker>(e1).is_none());
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 97);
+    let ch = app.world.get::<ComponentHistory<Enemy>>(e1).unwrap();
+    assert!(ch.alive_at_frame(3));
+    assert!(ch.alive_at_frame(5));
+
+    // and it isn't despawned once the rollback window elapses
+    for _ in 0..TEST_ROLLBACK_WINDOW {
+        tick(&mut app);
+    }
+    assert_eq!(
+        app.world.get::<Enemy>(e1).unwrap().health,
+        97 - TEST_ROLLBACK_WINDOW as i32
+    );
+}
+
+#[test]
+fn predicted_despawn_redone_by_rollback() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, despawn_dead)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health: 5 }).id();
+
+    tick(&mut app); // frame 1
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+
+    // the server says e1 had less health than we thought, so dies a frame sooner
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 2 })
+        .unwrap();
+
+    tick(&mut app); // frame 4
+
+    assert!(app.world.get::<Enemy>(e1).is_none());
+    assert_eq!(app.world.get::<DespawnMarker>(e1).unwrap().0, Some(4));
+    let ch = app.world.get::<ComponentHistory<Enemy>>(e1).unwrap();
+    assert!(ch.alive_at_frame(3));
+    assert!(!ch.alive_at_frame(4));
+
+    while app.world.resource::<GameClock>().frame() < 4 + TEST_ROLLBACK_WINDOW {
+        tick(&mut app);
+    }
+    assert!(app.world.get_entity(e1).is_none());
+}
+
+#[test]
+fn authoritative_despawn_survives_rollback() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health: 10 }).id();
+    let e2 = app.world.spawn(Enemy { health: 10 }).id();
+
+    tick(&mut app); // frame 1
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+
+    // the server told us e1 died during frame 3
+    app.world.entity_mut(e1).despawn_at_frame(3).unwrap();
+    assert!(app.world.get::<AuthoritativeDespawn>(e1).is_some());
+
+    tick(&mut app); // frame 4
+
+    // a rollback to before the despawn, caused by a snapshot for another entity
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e2)
+        .unwrap()
+        .insert(2, Enemy { health: 100 })
+        .unwrap();
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 3);
+    assert!(app.world.get::<Enemy>(e1).is_none());
+    assert_eq!(
+        app.world.get::<DespawnMarker>(e1),
+        Some(&DespawnMarker(Some(3)))
+    );
+
+    while app.world.resource::<GameClock>().frame() < 3 + TEST_ROLLBACK_WINDOW {
+        tick(&mut app);
+    }
+    assert!(app.world.get_entity(e1).is_none());
+}
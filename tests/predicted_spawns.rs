@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Bullet {
+    distance: i32,
+}
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn move_bullets(mut q: Query<&mut Bullet>) {
+    for mut bullet in q.iter_mut() {
+        bullet.distance += 1;
+    }
+}
+
+fn setup(tw_config: TimewarpConfig) -> App {
+    let mut app = setup_test_app_with_config(tw_config);
+
+    app.register_rollback::<Bullet>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, move_bullets)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+    app
+}
+
+#[test]
+fn server_entity_takes_over_predicted_spawn() {
+    let mut app = setup(test_config());
+
+    tick(&mut app); // frame 1
+
+    // we fire a bullet
+    let predicted = app
+        .world
+        .spawn((Bullet { distance: 0 }, PredictedSpawn::new(SpawnKey(1))))
+        .id();
+
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+
+    assert_eq!(app.world.get::<Bullet>(predicted).unwrap().distance, 2);
+
+    // the server's entity for our bullet arrives
+    let server_entity = app.world.spawn(ServerSpawnKey(SpawnKey(1))).id();
+
+    tick(&mut app); // frame 4
+
+    assert!(app.world.get_entity(predicted).is_none());
+    assert!(app.world.get::<ServerSpawnKey>(server_entity).is_none());
+    assert_eq!(app.world.get::<Bullet>(server_entity).unwrap().distance, 3);
+    assert_eq!(
+        app.comp_val_at::<Bullet>(server_entity, 2)
+            .unwrap()
+            .distance,
+        1
+    );
+    assert!(app
+        .world
+        .get::<ServerSnapshot<Bullet>>(server_entity)
+        .is_some());
+
+    // server corrections to the adopted history cause a rollback
+    app.world
+        .get_mut::<ServerSnapshot<Bullet>>(server_entity)
+        .unwrap()
+        .insert(3, Bullet { distance: 10 })
+        .unwrap();
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.get::<Bullet>(server_entity).unwrap().distance, 12);
+}
+
+#[test]
+fn unmatched_predicted_spawn_times_out() {
+    let mut app = setup(test_config().with_predicted_spawn_timeout(3));
+
+    tick(&mut app); // frame 1
+
+    let predicted = app
+        .world
+        .spawn((Bullet { distance: 0 }, PredictedSpawn::new(SpawnKey(1))))
+        .id();
+
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+    tick(&mut app); // frame 4
+
+    assert!(app.world.get_entity(predicted).is_some());
+
+    tick(&mut app); // frame 5
+
+    // spawned during frame 1, nothing from the server after 3 frames
+    assert!(app.world.get_entity(predicted).is_none());
+}
@@ -0,0 +1,116 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+/// we only simulate predicted entities, the others get their values from snapshots
+fn move_predicted(mut q: Query<(Entity, &mut Position, Option<&PredictionMode>)>) {
+    for (entity, mut pos, opt_mode) in q.iter_mut() {
+        if opt_mode.is_some_and(|mode| !mode.is_predicted()) {
+            continue;
+        }
+        pos.x += 1.0;
+        info!("{entity:?} moved -> {pos:?}");
+    }
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Position {
+    x: f32,
+}
+
+impl TimewarpInterpolate for Position {
+    fn interpolate(&self, to: &Self, t: f32) -> Self {
+        Self {
+            x: self.x + (to.x - self.x) * t,
+        }
+    }
+}
+
+fn snapshot(app: &mut App, entity: Entity, frame: FrameNumber, x: f32) {
+    app.world
+        .get_mut::<ServerSnapshot<Position>>(entity)
+        .unwrap()
+        .insert(frame, Position { x })
+        .unwrap();
+}
+
+fn x(app: &App, entity: Entity) -> f32 {
+    app.world.get::<Position>(entity).unwrap().x
+}
+
+#[test]
+fn per_entity_prediction_modes() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Position>();
+    app.register_interpolation::<Position>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, move_predicted)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let local_player = app.world.spawn(Position { x: 0.0 }).id();
+    let remote_player = app
+        .world
+        .spawn((
+            Position { x: 0.0 },
+            PredictionMode::Interpolated { delay: 2 },
+        ))
+        .id();
+    let cosmetic = app
+        .world
+        .spawn((Position { x: 0.0 }, PredictionMode::Snapped))
+        .id();
+
+    tick(&mut app); // frame 1
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+    tick(&mut app); // frame 4
+
+    assert_eq!(x(&app, local_player), 4.0);
+
+    snapshot(&mut app, cosmetic, 3, 50.0);
+    snapshot(&mut app, remote_player, 2, 20.0);
+    snapshot(&mut app, remote_player, 4, 40.0);
+
+    tick(&mut app); // frame 5
+
+    // neither caused a rollback
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 0);
+    assert_eq!(x(&app, local_player), 5.0);
+    // snapped straight to the snapshot value
+    assert_eq!(x(&app, cosmetic), 50.0);
+    assert_eq!(app.comp_val_at::<Position>(cosmetic, 5).unwrap().x, 50.0);
+    // showing frame 3, halfway between the snapshots for 2 and 4
+    assert_eq!(x(&app, remote_player), 30.0);
+
+    tick(&mut app); // frame 6
+
+    // showing frame 4, which is the newest snapshot
+    assert_eq!(x(&app, remote_player), 40.0);
+
+    snapshot(&mut app, remote_player, 6, 80.0);
+    tick(&mut app); // frame 7
+
+    assert_eq!(x(&app, remote_player), 60.0);
+
+    // a misprediction for the local player:
+    snapshot(&mut app, local_player, 5, 100.0);
+    tick(&mut app); // frame 8
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(x(&app, local_player), 103.0);
+    // the others don't take part in the rollback
+    assert_eq!(x(&app, cosmetic), 50.0);
+    assert_eq!(x(&app, remote_player), 80.0);
+}
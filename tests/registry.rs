@@ -0,0 +1,133 @@
+use std::any::TypeId;
+
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Shield {
+    strength: i32,
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct ShipBlueprint;
+
+#[derive(Component, Reflect, Default, Debug, Clone, PartialEq)]
+#[reflect(Component)]
+struct Mana {
+    amount: i32,
+}
+
+fn inc_frame(mut game_clock: ResMut<GameClock>) {
+    game_clock.advance(1);
+}
+
+fn setup() -> App {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_rollback_with_correction_logging::<Shield>();
+    app.register_blueprint::<ShipBlueprint>();
+    app.register_type::<Mana>();
+    app.register_rollback_by_type_path(Mana::type_path());
+
+    app.add_systems(FixedUpdate, inc_frame.in_set(TimewarpTestSets::GameLogic));
+    app
+}
+
+#[test]
+fn registry_lists_registered_types() {
+    let app = setup();
+    let registry = app.world.resource::<TimewarpRegistry>();
+
+    assert_eq!(
+        registry
+            .types()
+            .iter()
+            .map(|reg| (reg.type_id, reg.kind))
+            .collect::<Vec<_>>(),
+        vec![
+            (TypeId::of::<Enemy>(), RegistrationKind::Rollback),
+            (TypeId::of::<Shield>(), RegistrationKind::Rollback),
+            (TypeId::of::<ShipBlueprint>(), RegistrationKind::Blueprint),
+            (TypeId::of::<Mana>(), RegistrationKind::DynamicRollback),
+        ]
+    );
+    let shield = registry
+        .get(TypeId::of::<Shield>(), RegistrationKind::Rollback)
+        .unwrap();
+    assert_eq!(shield.type_name, std::any::type_name::<Shield>());
+    assert!(shield.correction_logging);
+    assert!(!shield.snap_only);
+    assert!(registry.is_registered::<Enemy>(RegistrationKind::Rollback));
+    assert!(!registry.is_registered::<Enemy>(RegistrationKind::Blueprint));
+}
+
+#[test]
+fn duplicate_registrations_are_ignored() {
+    let mut app = setup();
+
+    app.register_rollback::<Enemy>();
+    app.register_rollback_snap_only::<Shield>();
+    app.register_rollback_bundle::<(Enemy, Shield)>();
+    app.register_rollback_by_type_id(TypeId::of::<Mana>());
+    // the static and dynamic, or plain and assembler, registrations of the same type
+    app.register_rollback::<Mana>();
+    app.register_blueprint_with_assembler::<ShipBlueprint, _>(|_, _| ());
+
+    let registry = app.world.resource::<TimewarpRegistry>();
+    assert_eq!(registry.types().len(), 4);
+    assert!(!registry.is_registered::<Mana>(RegistrationKind::Rollback));
+    assert!(!registry.is_registered::<ShipBlueprint>(RegistrationKind::BlueprintWithAssembler));
+    assert!(
+        !registry
+            .get(TypeId::of::<Shield>(), RegistrationKind::Rollback)
+            .unwrap()
+            .snap_only
+    );
+
+    // and it still runs, without duplicate systems recording the history twice
+    let e1 = app.world.spawn(Enemy { health: 10 }).id();
+    tick(&mut app);
+    tick(&mut app);
+    assert_eq!(app.comp_val_at::<Enemy>(e1, 2).unwrap().health, 10);
+}
+
+#[test]
+fn memory_usage_per_type() {
+    let mut app = setup();
+
+    app.world.spawn((Enemy { health: 10 }, Mana { amount: 5 }));
+    app.world.spawn(Enemy { health: 20 });
+
+    tick(&mut app);
+    tick(&mut app);
+
+    let usage = TimewarpRegistry::memory_usage(&mut app.world);
+    assert_eq!(usage.len(), 3);
+
+    let enemy = usage
+        .iter()
+        .find(|usage| usage.type_name == std::any::type_name::<Enemy>())
+        .unwrap();
+    assert_eq!(enemy.entities, 2);
+    assert!(
+        enemy.bytes >= 2 * TEST_ROLLBACK_WINDOW as usize * std::mem::size_of::<Option<Enemy>>()
+    );
+
+    let shield = usage
+        .iter()
+        .find(|usage| usage.type_name == std::any::type_name::<Shield>())
+        .unwrap();
+    assert_eq!(shield.entities, 0);
+    assert_eq!(shield.bytes, 0);
+
+    let mana = usage
+        .iter()
+        .find(|usage| usage.type_name == Mana::type_path())
+        .unwrap();
+    assert_eq!(mana.entities, 1);
+    assert!(mana.bytes > 0);
+}
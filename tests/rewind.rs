@@ -0,0 +1,190 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<(Entity, &mut Enemy, &EntName)>) {
+    for (entity, mut enemy, name) in q.iter_mut() {
+        enemy.health -= 1;
+        info!("{entity:?} took 1 damage -> {enemy:?} {name:?}");
+    }
+}
+
+fn grow_hitboxes(mut q: Query<&mut Hitbox>) {
+    for mut hitbox in q.iter_mut() {
+        hitbox.radius += 1;
+    }
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Hitbox {
+    radius: u32,
+}
+
+/// server-side lag compensation: check what a hitbox looked like a few frames ago,
+/// without disturbing the present.
+#[test]
+fn rewind_world_for_lag_compensation() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_rollback::<Hitbox>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, grow_hitboxes)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app
+        .world
+        .spawn((
+            Enemy { health: 10 },
+            EntName {
+                name: "E1".to_owned(),
+            },
+            Hitbox { radius: 0 },
+        ))
+        .id();
+    let e2 = app
+        .world
+        .spawn((
+            Enemy { health: 100 },
+            EntName {
+                name: "E2".to_owned(),
+            },
+            Hitbox { radius: 10 },
+        ))
+        .id();
+
+    tick(&mut app); // frame 1
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+    tick(&mut app); // frame 4
+
+    // e3 doesn't exist at frame 2, so won't be rewound, and the closure is told to skip it
+    let e3 = app.world.spawn(Hitbox { radius: 50 }).id();
+
+    tick(&mut app); // frame 5
+
+    let change_tick = app.world.change_tick();
+
+    let radius_at_2 = app
+        .world
+        .with_rewound_to(2, |world, rewound| {
+            assert_eq!(world.get::<Enemy>(e1).unwrap().health, 8);
+            assert_eq!(world.get::<Enemy>(e2).unwrap().health, 98);
+            assert_eq!(world.get::<Hitbox>(e2).unwrap().radius, 12);
+            assert_eq!(world.get::<Hitbox>(e3).unwrap().radius, 51);
+            assert!(rewound.contains(e1));
+            assert!(rewound.contains(e2));
+            assert!(!rewound.contains(e3));
+            assert_eq!(rewound.len(), 2);
+            world.get::<Hitbox>(e1).unwrap().radius
+        })
+        .unwrap();
+    assert_eq!(radius_at_2, 2);
+
+    // present values are back:
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 5);
+    assert_eq!(app.world.get::<Hitbox>(e1).unwrap().radius, 5);
+    assert_eq!(app.world.get::<Hitbox>(e2).unwrap().radius, 15);
+    // history untouched:
+    assert_eq!(app.comp_val_at::<Hitbox>(e1, 2).unwrap().radius, 2);
+    assert_eq!(app.comp_val_at::<Hitbox>(e1, 5).unwrap().radius, 5);
+    // change detection untouched:
+    let ticks = app.world.entity(e1).get_change_ticks::<Hitbox>().unwrap();
+    assert!(!ticks.is_changed(change_tick, app.world.change_tick()));
+
+    // only rewind hitboxes for e1:
+    let filter = RewindFilter::all()
+        .with_entities([e1])
+        .with_component::<Hitbox>();
+    app.world
+        .with_rewound_to_filtered(3, &filter, |world, rewound| {
+            assert_eq!(world.get::<Hitbox>(e1).unwrap().radius, 3);
+            assert_eq!(world.get::<Hitbox>(e2).unwrap().radius, 15);
+            assert_eq!(world.get::<Enemy>(e1).unwrap().health, 5);
+            assert!(!rewound.contains(e2));
+        })
+        .unwrap();
+    assert_eq!(app.world.get::<Hitbox>(e1).unwrap().radius, 5);
+
+    // and the simulation carries on as normal
+    tick(&mut app); // frame 6
+    assert_eq!(app.world.get::<Hitbox>(e1).unwrap().radius, 6);
+    assert_eq!(app.comp_val_at::<Hitbox>(e1, 6).unwrap().radius, 6);
+}
+
+#[test]
+fn rewind_restores_present_if_closure_panics() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Hitbox>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, grow_hitboxes)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Hitbox { radius: 0 }).id();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        app.world.with_rewound_to(2, |world, _| {
+            assert_eq!(world.get::<Hitbox>(e1).unwrap().radius, 2);
+            panic!("hit validation failed");
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(app.world.get::<Hitbox>(e1).unwrap().radius, 5);
+}
+
+#[test]
+fn rewind_outside_rollback_window_fails() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Hitbox>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, grow_hitboxes)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Hitbox { radius: 0 }).id();
+
+    for _ in 0..(TEST_ROLLBACK_WINDOW + 5) {
+        tick(&mut app);
+    }
+    let current_frame = app.world.resource::<GameClock>().frame();
+
+    assert_eq!(
+        app.world.with_rewound_to(current_frame + 1, |_, _| ()),
+        Err(TimewarpError::FrameInFuture)
+    );
+    assert_eq!(
+        app.world
+            .with_rewound_to(current_frame - TEST_ROLLBACK_WINDOW, |_, _| ()),
+        Err(TimewarpError::FrameTooOld)
+    );
+    let oldest = current_frame - TEST_ROLLBACK_WINDOW + 1;
+    let radius = app
+        .world
+        .with_rewound_to(oldest, |world, _| world.get::<Hitbox>(e1).unwrap().radius)
+        .unwrap();
+    assert_eq!(radius, oldest);
+}
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<(Entity, &mut Enemy, &EntName)>) {
+    for (entity, mut enemy, name) in q.iter_mut() {
+        enemy.health -= 1;
+        info!("{entity:?} took 1 damage -> {enemy:?} {name:?}");
+    }
+}
+
+#[derive(Clone, Debug, Component, PartialEq)]
+struct FooBlueprint;
+
+/// servers record history, but don't get snapshots or do rollbacks.
+#[test]
+fn server_mode_only_records_history() {
+    let mut app = setup_test_app_with_mode(TimewarpMode::Server);
+
+    app.register_rollback::<Enemy>();
+    app.register_blueprint::<FooBlueprint>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app
+        .world
+        .spawn((
+            Enemy { health: 10 },
+            EntName {
+                name: "E1".to_owned(),
+            },
+        ))
+        .id();
+
+    tick(&mut app); // frame 1
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+
+    assert!(app.world.get::<ComponentHistory<Enemy>>(e1).is_some());
+    assert!(app.world.get::<ServerSnapshot<Enemy>>(e1).is_none());
+    assert_eq!(app.comp_val_at::<Enemy>(e1, 2).unwrap().health, 8);
+    assert_eq!(app.comp_val_at::<Enemy>(e1, 3).unwrap().health, 7);
+
+    // past-frame modifications don't rollback on the server
+    app.world
+        .entity_mut(e1)
+        .insert(AssembleBlueprintAtFrame::new(2, FooBlueprint));
+    app.world
+        .entity_mut(e1)
+        .remove_component_at_end_of_frame::<Enemy>(2);
+
+    tick(&mut app); // frame 4
+
+    assert_eq!(
+        app.world
+            .get_resource::<RollbackStats>()
+            .unwrap()
+            .num_rollbacks,
+        0
+    );
+    assert!(app.world.get_resource::<PreviousRollback>().is_none());
+    assert_eq!(app.world.resource::<GameClock>().frame(), 4);
+    // the blueprint for a past frame is unwrapped straight away
+    assert_eq!(app.world.get::<FooBlueprint>(e1), Some(&FooBlueprint));
+    assert!(app
+        .world
+        .get::<AssembleBlueprintAtFrame<FooBlueprint>>(e1)
+        .is_none());
+    // the removal applies now, agreeing with the history
+    assert!(app.world.get::<Enemy>(e1).is_none());
+    assert_eq!(app.comp_val_at::<Enemy>(e1, 2).unwrap().health, 8);
+    assert!(!app
+        .world
+        .get::<ComponentHistory<Enemy>>(e1)
+        .unwrap()
+        .alive_at_frame(3));
+
+    // despawn markers work as normal
+    app.world.entity_mut(e1).insert(DespawnMarker::new());
+    tick(&mut app); // frame 5
+    assert!(app.world.get::<Enemy>(e1).is_none());
+    for _ in 0..TEST_ROLLBACK_WINDOW {
+        tick(&mut app);
+    }
+    assert!(app.world.get_entity(e1).is_none());
+}
@@ -0,0 +1,71 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<(Entity, &mut Enemy)>) {
+    for (entity, mut enemy) in q.iter_mut() {
+        enemy.health -= 1;
+        info!("{entity:?} took 1 damage -> {enemy:?}");
+    }
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Score(u32);
+
+#[test]
+fn snap_only_components_dont_rollback() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_rollback_snap_only::<Score>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn((Enemy { health: 100 }, Score(0))).id();
+
+    tick(&mut app); // frame 1
+    tick(&mut app); // frame 2
+    tick(&mut app); // frame 3
+    tick(&mut app); // frame 4
+
+    // server says we'd scored by frame 2:
+    app.world
+        .get_mut::<ServerSnapshot<Score>>(e1)
+        .unwrap()
+        .insert(2, Score(1))
+        .unwrap();
+
+    tick(&mut app); // frame 5
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 0);
+    assert_eq!(app.world.get::<Score>(e1).unwrap().0, 1);
+    assert_eq!(app.comp_val_at::<Score>(e1, 2).unwrap().0, 1);
+    assert_eq!(app.comp_val_at::<Score>(e1, 5).unwrap().0, 1);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 95);
+
+    // a snapshot for a rollback component still triggers a rollback,
+    // which restores the snapped value from history:
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(2, Enemy { health: 50 })
+        .unwrap();
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 46);
+    assert_eq!(app.world.get::<Score>(e1).unwrap().0, 1);
+}
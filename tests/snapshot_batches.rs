@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<(Entity, &mut Enemy)>) {
+    for (entity, mut enemy) in q.iter_mut() {
+        enemy.health -= 1;
+        info!("{entity:?} took 1 damage -> {enemy:?}");
+    }
+}
+
+fn snapshot(app: &mut App, entity: Entity, frame: FrameNumber, health: i32) {
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(entity)
+        .unwrap()
+        .insert(frame, Enemy { health })
+        .unwrap();
+}
+
+fn health(app: &App, entity: Entity) -> i32 {
+    app.world.get::<Enemy>(entity).unwrap().health
+}
+
+#[test]
+fn snapshot_batches_choose_rollback_frame() {
+    // the default strategy is Newest, which is wrong for partial updates
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health: 100 }).id();
+    let e2 = app.world.spawn(Enemy { health: 100 }).id();
+    let e3 = app.world.spawn(Enemy { health: 100 }).id();
+
+    for _ in 1..=6 {
+        tick(&mut app);
+    }
+
+    // two partial batches in one tick:
+    app.world.resource_mut::<SnapshotBatches>().begin(2);
+    snapshot(&mut app, e1, 2, 50);
+    app.world.resource_mut::<SnapshotBatches>().end(false);
+    app.world.resource_mut::<SnapshotBatches>().begin(3);
+    snapshot(&mut app, e2, 3, 50);
+    app.world.resource_mut::<SnapshotBatches>().end(false);
+
+    tick(&mut app); // frame 7
+
+    // oldest, so we don't miss e1's update
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 3);
+    assert_eq!(health(&app, e1), 45);
+    assert_eq!(health(&app, e2), 46);
+    assert_eq!(health(&app, e3), 93);
+
+    // a partial batch, then a complete one for a newer frame, which supersedes it:
+    app.world.resource_mut::<SnapshotBatches>().begin(4);
+    snapshot(&mut app, e3, 4, 10);
+    app.world.resource_mut::<SnapshotBatches>().end(false);
+    app.world.resource_mut::<SnapshotBatches>().begin(5);
+    snapshot(&mut app, e1, 5, 20);
+    snapshot(&mut app, e3, 5, 9);
+    // e2 isn't mentioned, so it's confirmed unchanged at frame 5
+    app.world.resource_mut::<SnapshotBatches>().end(true);
+
+    tick(&mut app); // frame 8
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 2);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 6);
+    assert_eq!(health(&app, e1), 17);
+    assert_eq!(health(&app, e2), 45);
+    assert_eq!(health(&app, e3), 6);
+    let status = app.world.get::<TimewarpStatus>(e2).unwrap();
+    assert_eq!(status.last_snap_frame(), 5);
+
+    // a complete batch where everything was predicted correctly supersedes older requests,
+    // so there's no rollback at all:
+    app.world.resource_mut::<SnapshotBatches>().begin(6);
+    snapshot(&mut app, e1, 6, 999);
+    app.world.resource_mut::<SnapshotBatches>().end(false);
+    app.world.resource_mut::<SnapshotBatches>().begin(7);
+    snapshot(&mut app, e1, 7, 18);
+    app.world.resource_mut::<SnapshotBatches>().end(true);
+
+    tick(&mut app); // frame 9
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 2);
+    assert_eq!(health(&app, e1), 16);
+
+    // a complete batch doesn't supersede requests that didn't come from snapshots:
+    app.world
+        .entity_mut(e2)
+        .insert(InsertComponentAtFrame::new(6, Enemy { health: 80 }));
+    app.world.resource_mut::<SnapshotBatches>().begin(8);
+    snapshot(&mut app, e1, 8, 17);
+    app.world.resource_mut::<SnapshotBatches>().end(true);
+
+    tick(&mut app); // frame 10
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 3);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 7);
+    assert_eq!(health(&app, e1), 15);
+    assert_eq!(health(&app, e2), 76);
+}
+
+/// without batches, snapshots for frames after the start of a rollback aren't applied while
+/// resimulating, the consolidation strategy decides which frame we rollback to.
+#[test]
+fn snapshots_not_applied_during_rollback_without_batches() {
+    let mut app = setup_test_app_with_config(
+        test_config().with_consolidation_strategy(RollbackConsolidationStrategy::Oldest),
+    );
+
+    app.register_rollback::<Enemy>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn(Enemy { health: 100 }).id();
+    let e2 = app.world.spawn(Enemy { health: 100 }).id();
+
+    for _ in 1..=6 {
+        tick(&mut app);
+    }
+
+    snapshot(&mut app, e1, 2, 50);
+    snapshot(&mut app, e2, 4, 50);
+
+    tick(&mut app); // frame 7
+
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 3);
+    assert!(!app.world.resource::<SnapshotBatches>().in_use());
+    assert_eq!(health(&app, e1), 45);
+    assert_eq!(health(&app, e2), 93);
+}
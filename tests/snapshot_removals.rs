@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<(Entity, &mut Enemy, Option<&Shield>)>) {
+    for (entity, mut enemy, opt_shield) in q.iter_mut() {
+        if opt_shield.is_none() {
+            enemy.health -= 1;
+            info!("{entity:?} took 1 damage -> {enemy:?}");
+        } else {
+            info!("{entity:?} took NO damage due to having a shield -> {enemy:?}");
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Shield;
+
+#[test]
+fn server_removes_component_via_snapshot() {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_rollback::<Shield>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+
+    let e1 = app.world.spawn((Enemy { health: 100 }, Shield)).id();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 100);
+
+    // the server says the shield was removed at frame 3:
+    app.world
+        .get_mut::<ServerSnapshot<Shield>>(e1)
+        .unwrap()
+        .insert_removal(3);
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 3);
+    assert!(app.world.get::<Shield>(e1).is_none());
+    // damage taken on frames 4, 5, 6
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 97);
+    let ch = app.world.get::<ComponentHistory<Shield>>(e1).unwrap();
+    assert!(ch.alive_at_frame(2));
+    assert!(!ch.alive_at_frame(3));
+
+    // we already know it was removed before 5, so no rollback:
+    app.world
+        .get_mut::<ServerSnapshot<Shield>>(e1)
+        .unwrap()
+        .insert_removal(5);
+
+    tick(&mut app); // frame 7
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+
+    // another rollback to frame 5 must respect the removal, even though the CH still has
+    // our original predicted shield value for frame 4:
+    app.world
+        .get_mut::<ServerSnapshot<Enemy>>(e1)
+        .unwrap()
+        .insert(4, Enemy { health: 50 })
+        .unwrap();
+
+    tick(&mut app); // frame 8
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 2);
+    assert!(app.world.get::<Shield>(e1).is_none());
+    // damage taken on frames 5, 6, 7, 8
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 46);
+}
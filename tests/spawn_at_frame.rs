@@ -0,0 +1,125 @@
+use bevy::{ecs::system::CommandQueue, prelude::*};
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+#[derive(Component, Debug, Clone, PartialEq)]
+struct Shield {
+    strength: i32,
+}
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<&mut Enemy>) {
+    for mut enemy in q.iter_mut() {
+        enemy.health -= 1;
+    }
+}
+
+fn recharge(mut q: Query<&mut Shield>) {
+    for mut shield in q.iter_mut() {
+        shield.strength += 10;
+    }
+}
+
+fn setup() -> App {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_rollback::<Shield>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage, recharge)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+    app
+}
+
+fn spawn_at_frame<B: Bundle>(app: &mut App, frame: FrameNumber, bundle: B) -> Entity {
+    let mut queue = CommandQueue::default();
+    let entity = Commands::new(&mut queue, &app.world)
+        .spawn_at_frame(frame, bundle)
+        .id();
+    queue.apply(&mut app.world);
+    entity
+}
+
+#[test]
+fn spawn_bundle_in_the_past() {
+    let mut app = setup();
+
+    for _ in 1..=4 {
+        tick(&mut app);
+    }
+
+    let e1 = spawn_at_frame(
+        &mut app,
+        2,
+        (
+            Enemy { health: 100 },
+            Shield { strength: 0 },
+            EntName {
+                name: "E1".to_owned(),
+            },
+        ),
+    );
+    assert!(app.world.get::<EntName>(e1).is_some());
+
+    tick(&mut app); // frame 5
+
+    let stats = app.world.resource::<RollbackStats>();
+    assert_eq!(stats.num_rollbacks, 1);
+    assert_eq!(stats.range_faults, 0);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 3);
+    assert_eq!(
+        app.world
+            .get::<TimewarpStatus>(e1)
+            .unwrap()
+            .rollback_triggers(),
+        1
+    );
+
+    assert!(app.comp_val_at::<Enemy>(e1, 1).is_none());
+    assert!(app.comp_val_at::<Shield>(e1, 1).is_none());
+    assert_eq!(app.comp_val_at::<Enemy>(e1, 2).unwrap().health, 100);
+    assert_eq!(app.comp_val_at::<Shield>(e1, 2).unwrap().strength, 0);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 97);
+    assert_eq!(app.world.get::<Shield>(e1).unwrap().strength, 30);
+}
+
+#[test]
+fn spawn_at_frame_outside_window_spawns_now() {
+    let mut app = setup();
+
+    for _ in 1..=15 {
+        tick(&mut app);
+    }
+
+    let e1 = spawn_at_frame(&mut app, 2, Enemy { health: 100 });
+
+    let events = app.world.resource::<Events<TimewarpCommandError>>();
+    assert_eq!(
+        events
+            .get_reader()
+            .read(events)
+            .copied()
+            .collect::<Vec<_>>(),
+        vec![TimewarpCommandError {
+            entity: e1,
+            frame: 2,
+            error: TimewarpError::FrameTooOldSnapped,
+        }]
+    );
+
+    tick(&mut app); // frame 16
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 0);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 99);
+    assert_eq!(app.comp_val_at::<Enemy>(e1, 16).unwrap().health, 99);
+}
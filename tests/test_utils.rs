@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use std::time::Duration;
 
 use bevy::prelude::*;
@@ -21,12 +23,22 @@ pub struct EntName {
     pub name: String,
 }
 
+pub fn test_config() -> TimewarpConfig {
+    TimewarpConfig::new(TimewarpTestSets::GameLogic, TimewarpTestSets::GameLogic)
+        .with_rollback_window(TEST_ROLLBACK_WINDOW)
+        .with_schedule(FixedUpdate)
+}
+
 pub fn setup_test_app() -> App {
-    let mut app = App::new();
+    setup_test_app_with_config(test_config())
+}
 
-    let tw_config = TimewarpConfig::new(TimewarpTestSets::GameLogic, TimewarpTestSets::GameLogic)
-        .with_rollback_window(TEST_ROLLBACK_WINDOW)
-        .with_schedule(FixedUpdate);
+pub fn setup_test_app_with_mode(mode: TimewarpMode) -> App {
+    setup_test_app_with_config(test_config().with_mode(mode))
+}
+
+pub fn setup_test_app_with_config(tw_config: TimewarpConfig) -> App {
+    let mut app = App::new();
 
     app.add_plugins(bevy::log::LogPlugin {
         level: bevy::log::Level::TRACE,
@@ -0,0 +1,188 @@
+use bevy::{ecs::system::CommandQueue, prelude::*};
+use bevy_timewarp::prelude::*;
+
+mod test_utils;
+use test_utils::*;
+
+#[derive(Clone, Debug, Component, PartialEq)]
+struct ShipBlueprint;
+
+/// not registered for rollback
+#[derive(Clone, Debug, Component, PartialEq)]
+struct Shield;
+
+fn inc_frame(mut game_clock: ResMut<GameClock>, rb: Option<Res<Rollback>>) {
+    game_clock.advance(1);
+    info!("FRAME --> {:?} rollback:{rb:?}", game_clock.frame());
+}
+
+fn take_damage(mut q: Query<&mut Enemy>) {
+    for mut enemy in q.iter_mut() {
+        enemy.health -= 1;
+    }
+}
+
+fn setup() -> App {
+    let mut app = setup_test_app();
+
+    app.register_rollback::<Enemy>();
+    app.register_blueprint::<ShipBlueprint>();
+
+    app.add_systems(
+        FixedUpdate,
+        (inc_frame, take_damage)
+            .chain()
+            .in_set(TimewarpTestSets::GameLogic),
+    );
+    app
+}
+
+/// what a system with `Commands` would do
+fn run_commands(app: &mut App, f: impl FnOnce(&mut Commands)) {
+    let mut queue = CommandQueue::default();
+    let mut commands = Commands::new(&mut queue, &app.world);
+    f(&mut commands);
+    queue.apply(&mut app.world);
+}
+
+fn command_errors(app: &App) -> Vec<TimewarpCommandError> {
+    let events = app.world.resource::<Events<TimewarpCommandError>>();
+    events.get_reader().read(events).copied().collect()
+}
+
+#[test]
+fn commands_apply_at_past_frames() {
+    let mut app = setup();
+
+    let e1 = app.world.spawn(Enemy { health: 10 }).id();
+    let e2 = app.world.spawn(Enemy { health: 10 }).id();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    run_commands(&mut app, |commands| {
+        commands.entity(e1).insert_at_frame(3, Enemy { health: 50 });
+        commands.entity(e2).despawn_at_frame(4);
+        commands
+            .spawn_empty()
+            .assemble_blueprint_at_frame(6, ShipBlueprint);
+    });
+    assert!(command_errors(&app).is_empty());
+
+    tick(&mut app); // frame 6
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 1);
+    assert_eq!(app.world.resource::<PreviousRollback>().0.range.start, 4);
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 47);
+    assert!(app.world.get::<Enemy>(e2).is_none());
+    assert!(app.world.get::<DespawnMarker>(e2).is_some());
+
+    tick(&mut app); // frame 7, blueprints are unwrapped 1 frame late
+
+    assert_eq!(
+        app.world.query::<&ShipBlueprint>().iter(&app.world).count(),
+        1
+    );
+
+    run_commands(&mut app, |commands| {
+        commands.entity(e1).remove_at_frame::<Enemy>(5);
+    });
+
+    tick(&mut app); // frame 8
+
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 2);
+    assert!(app.world.get::<Enemy>(e1).is_none());
+    assert_eq!(app.comp_val_at::<Enemy>(e1, 5).unwrap().health, 48);
+}
+
+#[test]
+fn command_errors_are_sent_as_events() {
+    let mut app = setup();
+
+    let e1 = app.world.spawn(Enemy { health: 100 }).id();
+
+    for _ in 1..=15 {
+        tick(&mut app);
+    }
+
+    run_commands(&mut app, |commands| {
+        commands.entity(e1).despawn_at_frame(2);
+        commands.entity(e1).remove_at_frame::<Enemy>(20);
+        commands.entity(e1).remove_at_frame::<Enemy>(3);
+        // not registered, so there's no history to remove it from
+        commands.entity(e1).remove_at_frame::<Shield>(14);
+        commands
+            .entity(e1)
+            .assemble_blueprint_at_frame(3, ShipBlueprint);
+    });
+
+    assert_eq!(
+        command_errors(&app),
+        vec![
+            TimewarpCommandError {
+                entity: e1,
+                frame: 2,
+                error: TimewarpError::FrameTooOld,
+            },
+            TimewarpCommandError {
+                entity: e1,
+                frame: 20,
+                error: TimewarpError::FrameInFuture,
+            },
+            TimewarpCommandError {
+                entity: e1,
+                frame: 3,
+                error: TimewarpError::FrameTooOld,
+            },
+            TimewarpCommandError {
+                entity: e1,
+                frame: 14,
+                error: TimewarpError::NoComponentHistory,
+            },
+            TimewarpCommandError {
+                entity: e1,
+                frame: 3,
+                error: TimewarpError::FrameTooOld,
+            },
+        ]
+    );
+    assert_eq!(app.world.get::<Enemy>(e1).unwrap().health, 85);
+    tick(&mut app);
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 0);
+    assert!(app
+        .world
+        .get::<AssembleBlueprintAtFrame<ShipBlueprint>>(e1)
+        .is_none());
+}
+
+#[test]
+fn commands_for_despawned_entities_send_errors() {
+    let mut app = setup();
+
+    let e1 = app.world.spawn(Enemy { health: 100 }).id();
+
+    for _ in 1..=5 {
+        tick(&mut app);
+    }
+
+    run_commands(&mut app, |commands| {
+        commands.entity(e1).despawn();
+        commands.entity(e1).insert_at_frame(3, Enemy { health: 50 });
+        commands.entity(e1).remove_at_frame::<Enemy>(3);
+        commands.entity(e1).despawn_at_frame(3);
+        commands
+            .entity(e1)
+            .assemble_blueprint_at_frame(3, ShipBlueprint);
+    });
+
+    assert_eq!(
+        command_errors(&app)
+            .iter()
+            .map(|ev| (ev.entity, ev.frame, ev.error))
+            .collect::<Vec<_>>(),
+        vec![(e1, 3, TimewarpError::NoSuchEntity); 4]
+    );
+    tick(&mut app);
+    assert_eq!(app.world.resource::<RollbackStats>().num_rollbacks, 0);
+}